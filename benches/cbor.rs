@@ -0,0 +1,227 @@
+use actix_cbor::{Cbor, CborBody, CborBufferPool, CborConfig};
+use actix_web::http::header;
+use actix_web::test::TestRequest;
+use actix_web::{FromRequest, Responder, web};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    id: u64,
+    name: String,
+    tags: Vec<String>,
+    score: f64,
+}
+
+/// Payload with `n` records, roughly 50 bytes each.
+fn records(n: usize) -> Vec<Record> {
+    (0..n as u64)
+        .map(|id| Record {
+            id,
+            name: format!("record-{}", id),
+            tags: vec!["a".to_owned(), "bc".to_owned()],
+            score: id as f64 / 3.0,
+        })
+        .collect()
+}
+
+const SIZES: &[usize] = &[1, 100, 10_000];
+
+fn request(body: &[u8], content_length: bool) -> TestRequest {
+    let req = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .set_payload(body.to_vec());
+
+    if content_length {
+        req.header(header::CONTENT_LENGTH, body.len())
+    } else {
+        req
+    }
+}
+
+fn extraction(c: &mut Criterion) {
+    let mut rt = actix_rt::System::new("bench");
+    let mut group = c.benchmark_group("extract");
+
+    for &n in SIZES {
+        let body = serde_cbor::to_vec(&records(n)).unwrap();
+        let limit = body.len();
+        group.throughput(Throughput::Bytes(body.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("content_length", n), &body, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true).to_http_parts();
+                rt.block_on(CborBody::<Vec<Record>>::new(&req, &mut pl, None).limit(limit))
+                    .unwrap()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("unknown_length", n), &body, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, false).to_http_parts();
+                rt.block_on(CborBody::<Vec<Record>>::new(&req, &mut pl, None).limit(limit))
+                    .unwrap()
+            })
+        });
+
+        let pool = CborBufferPool::new(16).max_capacity(limit);
+        group.bench_with_input(BenchmarkId::new("pooled", n), &body, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, false).app_data(pool.clone()).to_http_parts();
+                rt.block_on(CborBody::<Vec<Record>>::new(&req, &mut pl, None).limit(limit))
+                    .unwrap()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("incremental", n), &body, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true).to_http_parts();
+                rt.block_on(
+                    CborBody::<Vec<Record>>::new(&req, &mut pl, None)
+                        .limit(limit)
+                        .incremental(true),
+                )
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn extractor(c: &mut Criterion) {
+    let mut rt = actix_rt::System::new("bench");
+    let mut group = c.benchmark_group("extractor");
+
+    for &n in SIZES {
+        let body = serde_cbor::to_vec(&records(n)).unwrap();
+        group.throughput(Throughput::Bytes(body.len() as u64));
+
+        let config = CborConfig::default().limit(body.len());
+        group.bench_with_input(BenchmarkId::from_parameter(n), &body, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true).app_data(config.clone()).to_http_parts();
+                rt.block_on(Cbor::<Vec<Record>>::from_request(&req, &mut pl))
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn response(c: &mut Criterion) {
+    let mut rt = actix_rt::System::new("bench");
+    let mut group = c.benchmark_group("respond");
+    let req = TestRequest::default().to_http_request();
+
+    for &n in SIZES {
+        let data = records(n);
+        group.throughput(Throughput::Elements(n as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(n), &data, |b, data| {
+            b.iter(|| rt.block_on(Cbor(data).respond_to(&req)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// CBOR compared to actix-web's `Json<T>` for the same payloads.
+fn compare(c: &mut Criterion) {
+    let mut rt = actix_rt::System::new("bench");
+    let mut group = c.benchmark_group("compare");
+    let req = TestRequest::default().to_http_request();
+
+    for &n in SIZES {
+        let data = records(n);
+
+        let cbor = serde_cbor::to_vec(&data).unwrap();
+        let config = CborConfig::default().limit(cbor.len());
+        group.bench_with_input(BenchmarkId::new("extract_cbor", n), &cbor, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true).app_data(config.clone()).to_http_parts();
+                rt.block_on(Cbor::<Vec<Record>>::from_request(&req, &mut pl))
+                    .unwrap()
+            })
+        });
+
+        let json = serde_json::to_vec(&data).unwrap();
+        let config = web::JsonConfig::default().limit(json.len());
+        group.bench_with_input(BenchmarkId::new("extract_json", n), &json, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = TestRequest::default()
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::CONTENT_LENGTH, body.len())
+                    .set_payload(body.to_vec())
+                    .app_data(config.clone())
+                    .to_http_parts();
+                rt.block_on(web::Json::<Vec<Record>>::from_request(&req, &mut pl))
+                    .unwrap()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("respond_cbor", n), &data, |b, data| {
+            b.iter(|| rt.block_on(Cbor(data).respond_to(&req)).unwrap())
+        });
+
+        group.bench_with_input(BenchmarkId::new("respond_json", n), &data, |b, data| {
+            b.iter(|| rt.block_on(web::Json(data).respond_to(&req)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// Extraction of gzip compressed payloads.
+#[cfg(feature = "compress")]
+fn compressed(c: &mut Criterion) {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    let mut rt = actix_rt::System::new("bench");
+    let mut group = c.benchmark_group("compressed");
+
+    for &n in SIZES {
+        let body = serde_cbor::to_vec(&records(n)).unwrap();
+        let limit = body.len();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+        let compressed = encoder.finish().unwrap();
+        group.throughput(Throughput::Bytes(body.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("gzip", n), &compressed, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true)
+                    .header(header::CONTENT_ENCODING, "gzip")
+                    .to_http_parts();
+                let body = CborBody::<Vec<Record>>::new(&req, &mut pl, None)
+                    .limit(limit)
+                    .compressed_limit(body.len());
+                rt.block_on(body).unwrap()
+            })
+        });
+
+        let config = CborConfig::default().limit(limit).compressed_limit(compressed.len());
+        group.bench_with_input(BenchmarkId::new("gzip_extractor", n), &compressed, |b, body| {
+            b.iter(|| {
+                let (req, mut pl) = request(body, true)
+                    .header(header::CONTENT_ENCODING, "gzip")
+                    .app_data(config.clone())
+                    .to_http_parts();
+                rt.block_on(Cbor::<Vec<Record>>::from_request(&req, &mut pl))
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(not(feature = "compress"))]
+criterion_group!(benches, extraction, extractor, response, compare);
+#[cfg(feature = "compress")]
+criterion_group!(benches, extraction, extractor, response, compare, compressed);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+#!/usr/bin/env bash
+# Compare the benchmarks against a base revision and fail if any of them regressed.
+#
+# Usage: benches/regression.sh [base revision, default origin/master]
+#
+# The base revision is benchmarked in a temporary worktree and saved as the criterion baseline
+# `base`, then the working tree is benchmarked against it. Benchmarks missing from the base
+# revision are only recorded. Change is reported as a regression when it is statistically
+# significant and bigger than the noise threshold, `BENCH_NOISE_THRESHOLD` (default 0.05, i.e.
+# 5%), as shared CI runners are noisier than criterion's default of 1% allows for.
+set -euo pipefail
+
+base="${1:-origin/master}"
+noise="${BENCH_NOISE_THRESHOLD:-0.05}"
+root="$(git rev-parse --show-toplevel)"
+
+# both runs have to share the criterion output directory
+CARGO_TARGET_DIR="${CARGO_TARGET_DIR:-$root/target}"
+export CARGO_TARGET_DIR
+rm -rf "$CARGO_TARGET_DIR/criterion"
+
+worktree="$(mktemp -d)"
+output="$(mktemp)"
+trap 'git -C "$root" worktree remove --force "$worktree"; rm -f "$output"' EXIT
+
+git -C "$root" worktree add --detach "$worktree" "$base" >/dev/null
+(cd "$worktree" && cargo bench --all-features --bench cbor -- --save-baseline base)
+
+# a saved baseline is compared against before it is overwritten, which unlike `--baseline`
+# tolerates benchmarks the base revision does not have
+cd "$root"
+cargo bench --all-features --bench cbor -- --save-baseline base --noise-threshold "$noise" \
+    | tee "$output"
+
+if grep -q "Performance has regressed" "$output"; then
+    echo "Benchmarks regressed compared to $base" >&2
+    exit 1
+fi
@@ -0,0 +1,474 @@
+//! Derive macros for [actix-cbor](https://docs.rs/actix-cbor).
+//!
+//! Use them through the re-exports of the `cddl` feature of `actix-cbor` rather than directly.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, LitStr, Token, Type,
+};
+
+/// Derive `CddlSchema`, following the `#[serde(...)]` attributes of the type.
+#[proc_macro_derive(CddlSchema, attributes(serde))]
+pub fn derive_cddl_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let container = Container::from_attrs(&input.attrs)?;
+
+    let definition = match input.data {
+        Data::Struct(ref data) => struct_definition(&input.ident, &container, &data.fields)?,
+        Data::Enum(ref data) => {
+            let mut choices = Vec::new();
+            for variant in &data.variants {
+                let attrs = Variant::from_attrs(&variant.attrs)?;
+                if attrs.skip {
+                    continue;
+                }
+
+                let name = attrs.rename.unwrap_or_else(|| {
+                    rename_variant(&variant.ident.to_string(), container.rename_all.as_deref())
+                });
+                let rename_all = attrs.rename_all.or_else(|| container.rename_all_fields.clone());
+                choices.push(variant_definition(&container, &name, rename_all.as_deref(), &variant.fields)?);
+            }
+
+            quote! {{
+                let choices: ::std::vec::Vec<::std::string::String> = ::std::vec![#(#choices),*];
+                choices.join(" / ")
+            }}
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(&input.ident, "unions are not supported"));
+        }
+    };
+
+    let ident = &input.ident;
+
+    let type_params = input.generics.type_params().map(|param| param.ident.clone()).collect::<Vec<_>>();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause.predicates.push(parse_quote!(#param: ::actix_cbor::CddlSchema));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::actix_cbor::CddlSchema for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn cddl_type(generator: &mut ::actix_cbor::CddlGenerator) -> ::std::string::String {
+                generator.rule::<Self>(|generator| #definition)
+            }
+        }
+    })
+}
+
+/// Container attributes.
+#[derive(Default)]
+struct Container {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    rename_all_fields: Option<String>,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    transparent: bool,
+    default: bool,
+}
+
+impl Container {
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut container = Container::default();
+        parse_serde(attrs, |key, meta| {
+            match key {
+                "rename" => container.rename = name_value(&meta)?,
+                "rename_all" => container.rename_all = rename_rule(&meta)?,
+                "rename_all_fields" => container.rename_all_fields = rename_rule(&meta)?,
+                "tag" => container.tag = name_value(&meta)?,
+                "content" => container.content = name_value(&meta)?,
+                "untagged" => container.untagged = true,
+                "transparent" => container.transparent = true,
+                "default" => {
+                    container.default = true;
+                    skip_value(&meta)?;
+                }
+                _ => skip_value(&meta)?,
+            }
+            Ok(())
+        })?;
+        Ok(container)
+    }
+}
+
+/// Variant attributes.
+#[derive(Default)]
+struct Variant {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+}
+
+impl Variant {
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut variant = Variant::default();
+        parse_serde(attrs, |key, meta| {
+            match key {
+                "rename" => variant.rename = name_value(&meta)?,
+                "rename_all" => variant.rename_all = rename_rule(&meta)?,
+                "skip" => variant.skip = true,
+                _ => skip_value(&meta)?,
+            }
+            Ok(())
+        })?;
+        Ok(variant)
+    }
+}
+
+/// Field attributes.
+#[derive(Default)]
+struct Field {
+    rename: Option<String>,
+    skip: bool,
+    flatten: bool,
+    optional: bool,
+}
+
+impl Field {
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field = Field::default();
+        parse_serde(attrs, |key, meta| {
+            match key {
+                "rename" => field.rename = name_value(&meta)?,
+                "skip" => field.skip = true,
+                "flatten" => field.flatten = true,
+                // only present in one direction
+                "skip_serializing" | "skip_deserializing" => field.optional = true,
+                "default" | "skip_serializing_if" => {
+                    field.optional = true;
+                    skip_value(&meta)?;
+                }
+                _ => skip_value(&meta)?,
+            }
+            Ok(())
+        })?;
+        Ok(field)
+    }
+}
+
+/// Call `f` with the key of each item in the `#[serde(...)]` attributes.
+fn parse_serde(
+    attrs: &[Attribute],
+    mut f: impl FnMut(&str, ParseNestedMeta) -> syn::Result<()>,
+) -> syn::Result<()> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            let key = meta
+                .path
+                .get_ident()
+                .map(|ident| ident.to_string())
+                .unwrap_or_default();
+            f(&key, meta)
+        })?;
+    }
+    Ok(())
+}
+
+/// Value of `key = "value"`, or of the `serialize` half of `key(serialize = "..", deserialize = "..")`.
+fn name_value(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(Token![=]) {
+        return Ok(Some(meta.value()?.parse::<LitStr>()?.value()));
+    }
+
+    let (mut serialize, mut deserialize) = (None, None);
+    meta.parse_nested_meta(|inner| {
+        let value = inner.value()?.parse::<LitStr>()?.value();
+        if inner.path.is_ident("serialize") {
+            serialize = Some(value);
+        } else if inner.path.is_ident("deserialize") {
+            deserialize = Some(value);
+        }
+        Ok(())
+    })?;
+    Ok(serialize.or(deserialize))
+}
+
+/// Rule of a `rename_all` or `rename_all_fields` item, which must be one that serde knows.
+fn rename_rule(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    const RULES: &[&str] = &[
+        "lowercase",
+        "UPPERCASE",
+        "PascalCase",
+        "camelCase",
+        "snake_case",
+        "SCREAMING_SNAKE_CASE",
+        "kebab-case",
+        "SCREAMING-KEBAB-CASE",
+    ];
+
+    let rule = name_value(meta)?;
+    match rule {
+        Some(ref rule) if !RULES.contains(&rule.as_str()) => {
+            Err(meta.error(format!("unknown rename rule `{}`", rule)))
+        }
+        rule => Ok(rule),
+    }
+}
+
+/// Consume the value of an item that does not affect the CDDL, if it has one.
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let content;
+        syn::parenthesized!(content in meta.input);
+        content.parse::<TokenStream2>()?;
+    }
+    Ok(())
+}
+
+fn cddl_type(ty: &Type) -> TokenStream2 {
+    quote! { <#ty as ::actix_cbor::CddlSchema>::cddl_type(generator) }
+}
+
+/// CDDL text string literal.
+fn literal(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn struct_definition(
+    ident: &syn::Ident,
+    container: &Container,
+    fields: &Fields,
+) -> syn::Result<TokenStream2> {
+    if container.transparent {
+        for field in fields.iter() {
+            if !Field::from_attrs(&field.attrs)?.skip {
+                return Ok(cddl_type(&field.ty));
+            }
+        }
+    }
+
+    match fields {
+        Fields::Named(_) => {
+            // like internally tagged variants, with the name of the struct as the tag
+            let tag = container.tag.as_ref().map(|tag| {
+                let name = container.rename.clone().unwrap_or_else(|| ident.to_string());
+                let tag = format!("{}: {}", literal(tag), literal(&name));
+                quote! { entries.push(::std::string::String::from(#tag)); }
+            });
+            let entries = map_entries(container.rename_all.as_deref(), fields, container.default)?;
+            Ok(quote! {{
+                let mut entries: ::std::vec::Vec<::std::string::String> = ::std::vec::Vec::new();
+                #tag
+                #(#entries)*
+                ::actix_cbor::__cddl_map(&entries)
+            }})
+        }
+        _ if container.tag.is_some() => Err(syn::Error::new_spanned(
+            ident,
+            "#[serde(tag = \"...\")] can only be used on enums and structs with named fields",
+        )),
+        _ => tuple_definition(fields),
+    }
+}
+
+/// Content of a variant, `None` for unit variants.
+fn variant_content(rename_all: Option<&str>, fields: &Fields) -> syn::Result<Option<TokenStream2>> {
+    match fields {
+        Fields::Unit => Ok(None),
+        Fields::Named(_) => {
+            let entries = map_entries(rename_all, fields, false)?;
+            Ok(Some(quote! {{
+                let mut entries: ::std::vec::Vec<::std::string::String> = ::std::vec::Vec::new();
+                #(#entries)*
+                ::actix_cbor::__cddl_map(&entries)
+            }}))
+        }
+        Fields::Unnamed(_) => tuple_definition(fields).map(Some),
+    }
+}
+
+fn variant_definition(
+    container: &Container,
+    name: &str,
+    rename_all: Option<&str>,
+    fields: &Fields,
+) -> syn::Result<TokenStream2> {
+    let name = literal(name);
+
+    if container.untagged {
+        return Ok(match variant_content(rename_all, fields)? {
+            Some(content) => content,
+            None => quote! { ::std::string::String::from("null") },
+        });
+    }
+
+    let tag = match container.tag {
+        Some(ref tag) => format!("{}: {}", literal(tag), name),
+        None => {
+            return Ok(match variant_content(rename_all, fields)? {
+                Some(content) => quote! { ::std::format!("{{ {}: {} }}", #name, #content) },
+                None => quote! { ::std::string::String::from(#name) },
+            });
+        }
+    };
+
+    // adjacently tagged
+    if let Some(ref content_key) = container.content {
+        let content_key = literal(content_key);
+        return Ok(match variant_content(rename_all, fields)? {
+            Some(content) => quote! { ::std::format!("{{ {}, {}: {} }}", #tag, #content_key, #content) },
+            None => quote! { ::std::format!("{{ {} }}", #tag) },
+        });
+    }
+
+    // internally tagged, with the tag next to the fields of the content
+    let entries = match fields {
+        Fields::Unit => Vec::new(),
+        Fields::Named(_) => map_entries(rename_all, fields, false)?,
+        Fields::Unnamed(ref unnamed) => {
+            let ty = &unnamed.unnamed.first().unwrap().ty;
+            let ty = cddl_type(ty);
+            vec![quote! { entries.push(::actix_cbor::CddlGenerator::flatten(#ty)); }]
+        }
+    };
+    Ok(quote! {{
+        let mut entries: ::std::vec::Vec<::std::string::String> = ::std::vec![::std::string::String::from(#tag)];
+        #(#entries)*
+        ::actix_cbor::__cddl_map(&entries)
+    }})
+}
+
+/// Statements pushing the group entries of named fields to `entries`.
+fn map_entries(rename_all: Option<&str>, fields: &Fields, default: bool) -> syn::Result<Vec<TokenStream2>> {
+    let mut entries = Vec::new();
+
+    for field in fields.iter() {
+        let attrs = Field::from_attrs(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let ty = cddl_type(&field.ty);
+        if attrs.flatten {
+            entries.push(quote! { entries.push(::actix_cbor::CddlGenerator::flatten(#ty)); });
+            continue;
+        }
+
+        let ident = field.ident.as_ref().unwrap().to_string();
+        let ident = ident.strip_prefix("r#").unwrap_or(&ident);
+        let name = attrs.rename.unwrap_or_else(|| rename_field(ident, rename_all));
+        let optional = if attrs.optional || default || is_option(&field.ty) { "? " } else { "" };
+        let key = format!("{}{}: ", optional, literal(&name));
+
+        entries.push(quote! { entries.push(::std::format!("{}{}", #key, #ty)); });
+    }
+
+    Ok(entries)
+}
+
+/// Tuple structs and variants: newtypes are their inner type, others are arrays.
+fn tuple_definition(fields: &Fields) -> syn::Result<TokenStream2> {
+    let mut items = Vec::new();
+    for field in fields.iter() {
+        if !Field::from_attrs(&field.attrs)?.skip {
+            items.push(cddl_type(&field.ty));
+        }
+    }
+
+    Ok(match fields {
+        Fields::Unit => quote! { ::std::string::String::from("null") },
+        Fields::Unnamed(ref unnamed) if unnamed.unnamed.len() == 1 && items.len() == 1 => {
+            items.pop().unwrap()
+        }
+        _ => quote! {{
+            let items: ::std::vec::Vec<::std::string::String> = ::std::vec![#(#items),*];
+            ::std::format!("[{}]", items.join(", "))
+        }},
+    })
+}
+
+/// Whether a field is an `Option`, which serde treats as optional when deserializing.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Apply a `rename_all` rule to a field name, which is in snake case.
+fn rename_field(field: &str, rule: Option<&str>) -> String {
+    match rule {
+        Some("UPPERCASE") | Some("SCREAMING_SNAKE_CASE") => field.to_ascii_uppercase(),
+        Some("PascalCase") => pascal_case(field),
+        Some("camelCase") => {
+            let pascal = pascal_case(field);
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                None => pascal,
+            }
+        }
+        Some("kebab-case") => field.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => field.to_ascii_uppercase().replace('_', "-"),
+        _ => field.to_owned(),
+    }
+}
+
+/// Apply a `rename_all` rule to a variant name, which is in Pascal case.
+fn rename_variant(variant: &str, rule: Option<&str>) -> String {
+    match rule {
+        Some("lowercase") => variant.to_ascii_lowercase(),
+        Some("UPPERCASE") => variant.to_ascii_uppercase(),
+        Some("camelCase") => {
+            let mut chars = variant.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        }
+        Some("snake_case") => snake_case(variant),
+        Some("SCREAMING_SNAKE_CASE") => snake_case(variant).to_ascii_uppercase(),
+        Some("kebab-case") => snake_case(variant).replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => snake_case(variant).to_ascii_uppercase().replace('_', "-"),
+        _ => variant.to_owned(),
+    }
+}
+
+fn pascal_case(field: &str) -> String {
+    let mut pascal = String::with_capacity(field.len());
+    let mut capitalize = true;
+    for c in field.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            pascal.push(c.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            pascal.push(c);
+        }
+    }
+    pascal
+}
+
+fn snake_case(variant: &str) -> String {
+    let mut snake = String::with_capacity(variant.len() + 4);
+    for (i, c) in variant.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
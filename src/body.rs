@@ -1,19 +1,76 @@
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use actix_http::{HttpMessage, Payload};
+#[cfg(feature = "compress")]
+use actix_http::error::PayloadError;
 use actix_http::http::header::CONTENT_LENGTH;
 #[cfg(feature = "compress")]
+use actix_http::http::header::{CONTENT_ENCODING, ContentEncoding};
+#[cfg(feature = "compress")]
+use actix_http::PayloadStream;
+#[cfg(feature = "compress")]
 use actix_web::dev::Decompress;
 use actix_web::HttpRequest;
-use actix_web::web::BytesMut;
-use futures_util::future::{FutureExt, LocalBoxFuture};
-use futures_util::StreamExt;
+use actix_web::web::{Bytes, BytesMut};
+use futures_util::future::{FutureExt, LocalBoxFuture, ready};
+#[cfg(feature = "compress")]
+use futures_util::ready;
+use futures_util::stream::{Stream, StreamExt};
 use serde::de::DeserializeOwned;
 
-use crate::CborPayloadError;
+use crate::coap::{is_cbor_media_type, is_sequence};
+use crate::config::ContentTypePredicate;
+#[cfg(feature = "compress")]
+use crate::dictionary::{Dictionary, ZstdDecoder};
+#[cfg(feature = "compress")]
+use crate::ZstdDictionaries;
+use crate::validate::{DEFAULT_MAX_DEPTH, Validator};
+use crate::{CborBufferPool, CborPayloadError, CoapContentFormats, DEFAULT_LIMIT};
+
+/// Check a mime type against `application/cbor` and the optional custom predicate.
+pub(crate) fn is_cbor_content_type(mime: &str, ctype: Option<&ContentTypePredicate>) -> bool {
+    mime == "application/cbor"
+        || mime == "cbor"
+        || ctype.is_some_and(|predicate| predicate(mime))
+}
+
+/// Initial buffer capacity when the size of the payload is not known up front.
+const INITIAL_CAPACITY: usize = 8192;
+
+/// Buffer capacity for a payload of `length` bytes. The length is only trusted up to `limit`, as
+/// larger payloads are rejected anyway.
+pub(crate) fn initial_capacity(length: Option<usize>, limit: usize) -> usize {
+    length.unwrap_or(INITIAL_CAPACITY).min(limit)
+}
+
+/// Collect a payload stream into `body`, failing as soon as it grows past `limit` or, if a
+/// validator is given, as soon as a chunk is not valid.
+pub(crate) async fn read_body<S, E>(
+    mut stream: S,
+    mut body: BytesMut,
+    limit: usize,
+    mut validator: Option<Validator>,
+) -> Result<BytesMut, CborPayloadError>
+    where
+        S: Stream<Item=Result<Bytes, E>> + Unpin,
+        CborPayloadError: From<E>,
+{
+    while let Some(item) = stream.next().await {
+        let chunk = item?;
+        if (body.len() + chunk.len()) > limit {
+            return Err(CborPayloadError::Overflow);
+        }
+
+        if let Some(ref mut validator) = validator {
+            validator.feed(&chunk).map_err(CborPayloadError::Malformed)?;
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body)
+}
 
 /// Request's payload cbor parser, it resolves to a deserialized `T` value.
 /// This future could be used with `ServiceRequest` and `ServiceFromRequest`.
@@ -22,13 +79,34 @@ use crate::CborPayloadError;
 ///
 /// * content type is not `application/cbor`
 ///   (unless specified in [`CborConfig`](struct.CborConfig.html))
-/// * content length is greater than 256k
+/// * with [`CoapContentFormats`](struct.CoapContentFormats.html) in app data, the Content-Format
+///   header names an unknown format
+/// * content length is greater than [`DEFAULT_LIMIT`](constant.DEFAULT_LIMIT.html) (32kB), unless
+///   changed with [`limit`](#method.limit)
+/// * with the `compress` feature, the compressed payload is greater than the
+///   [`compressed_limit`](#method.compressed_limit)
+/// * with the `compress` feature, a zstd payload names a dictionary that is not registered in
+///   [`ZstdDictionaries`](struct.ZstdDictionaries.html)
+/// * in [`incremental`](#method.incremental) mode, the payload is found to be malformed while it
+///   is being read
+///
+/// The payload buffer is preallocated from the `Content-Length`, up to the limit, and taken from
+/// the [`CborBufferPool`](struct.CborBufferPool.html) in app data, if any.
 pub struct CborBody<U> {
     pub(crate) limit: usize,
-    pub(crate) length: Option<usize>,
     #[cfg(feature = "compress")]
-    pub(crate) stream: Option<Decompress<Payload>>,
-    #[cfg(not(feature = "compress"))]
+    pub(crate) compressed_limit: Option<usize>,
+    #[cfg(feature = "compress")]
+    pub(crate) encoding: ContentEncoding,
+    /// `Some` for zstd payloads, which actix-web does not decompress, with the dictionary if any
+    #[cfg(feature = "compress")]
+    pub(crate) zstd: Option<Option<Dictionary>>,
+    pub(crate) incremental: bool,
+    pub(crate) max_depth: usize,
+    /// The payload is a CBOR sequence, decoded as an array of its items
+    pub(crate) sequence: bool,
+    pub(crate) length: Option<usize>,
+    pub(crate) pool: Option<CborBufferPool>,
     pub(crate) stream: Option<Payload>,
     pub(crate) err: Option<CborPayloadError>,
     pub(crate) fut: Option<LocalBoxFuture<'static, Result<U, CborPayloadError>>>,
@@ -42,24 +120,20 @@ impl<U> CborBody<U>
     pub fn new(
         req: &HttpRequest,
         payload: &mut Payload,
-        ctype: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+        ctype: Option<ContentTypePredicate>,
     ) -> Self {
-        // check content-type
-        let mime = req.content_type();
-        let is_good_mime =
-            mime == "application/cbor"
-                || mime == "cbor"
-                || ctype.as_ref().map_or(false, |predicate| predicate(mime));
-
-        if !is_good_mime {
-            return CborBody {
-                limit: 262_144,
-                length: None,
-                stream: None,
-                fut: None,
-                err: Some(CborPayloadError::ContentType),
-            };
-        }
+        // check content-type, or the CoAP Content-Format a proxy translated it to
+        let sequence = match CoapContentFormats::from_req(req).map(|formats| formats.select(req)) {
+            Some(Err(())) => return Self::error(CborPayloadError::ContentType),
+            Some(Ok(Some(media_type))) if is_cbor_media_type(media_type, ctype.as_ref()) => {
+                is_sequence(media_type)
+            }
+            Some(Ok(Some(_))) => return Self::error(CborPayloadError::ContentType),
+            _ if !is_cbor_content_type(req.content_type(), ctype.as_ref()) => {
+                return Self::error(CborPayloadError::ContentType);
+            }
+            _ => false,
+        };
 
         let len = req
             .headers()
@@ -68,24 +142,213 @@ impl<U> CborBody<U>
             .and_then(|s| s.parse::<usize>().ok());
 
         #[cfg(feature = "compress")]
-            let payload = Decompress::from_headers(payload.take(), req.headers());
-        #[cfg(not(feature = "compress"))]
-            let payload = payload.take();
+            let content_encoding = req
+            .headers()
+            .get(&CONTENT_ENCODING)
+            .and_then(|enc| enc.to_str().ok());
+
+        #[cfg(feature = "compress")]
+            let encoding = content_encoding
+            .map(ContentEncoding::from)
+            .unwrap_or(ContentEncoding::Identity);
+
+        // trimmed like `ContentEncoding::from`, which has no zstd variant
+        #[cfg(feature = "compress")]
+            let zstd = if content_encoding.is_some_and(|enc| enc.trim().eq_ignore_ascii_case("zstd")) {
+            match ZstdDictionaries::from_req(req).map(|dicts| dicts.select(req)) {
+                Some(Err(())) => return Self::error(CborPayloadError::UnknownDictionary),
+                Some(Ok(dictionary)) => Some(dictionary),
+                None => Some(None),
+            }
+        } else {
+            None
+        };
 
         CborBody {
-            limit: 262_144,
+            limit: DEFAULT_LIMIT,
+            #[cfg(feature = "compress")]
+            compressed_limit: None,
+            #[cfg(feature = "compress")]
+            encoding,
+            #[cfg(feature = "compress")]
+            zstd,
+            incremental: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            sequence,
             length: len,
-            stream: Some(payload),
+            pool: CborBufferPool::from_req(req),
+            stream: Some(payload.take()),
             fut: None,
             err: None,
         }
     }
 
-    /// Change max size of payload. By default max size is 256Kb
+    /// `CborBody` that fails with `err`.
+    fn error(err: CborPayloadError) -> Self {
+        CborBody {
+            limit: DEFAULT_LIMIT,
+            #[cfg(feature = "compress")]
+            compressed_limit: None,
+            #[cfg(feature = "compress")]
+            encoding: ContentEncoding::Identity,
+            #[cfg(feature = "compress")]
+            zstd: None,
+            incremental: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            sequence: false,
+            length: None,
+            pool: None,
+            stream: None,
+            fut: None,
+            err: Some(err),
+        }
+    }
+
+    /// Change max size of payload. By default max size is 32Kb
+    ///
+    /// With the `compress` feature this limits the decompressed size of the payload.
     pub fn limit(mut self, limit: usize) -> Self {
         self.limit = limit;
         self
     }
+
+    /// Validate the structure of the payload chunk by chunk as it is read, so that malformed
+    /// payloads are rejected at the first offending byte. Disabled by default.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Change max nesting depth checked in [`incremental`](#method.incremental) mode. By default
+    /// max depth is 128.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Change max size of the compressed payload as received on the wire. By default this is the
+    /// same as [`limit`](#method.limit).
+    ///
+    /// This has no effect on payloads without a `Content-Encoding`.
+    #[cfg(feature = "compress")]
+    pub fn compressed_limit(mut self, limit: usize) -> Self {
+        self.compressed_limit = Some(limit);
+        self
+    }
+
+    /// Resolve to the decoded value, or to the error along with the raw body if it was read in full
+    /// before decoding failed.
+    pub(crate) fn raw_future(&mut self) -> LocalBoxFuture<'static, Result<U, (CborPayloadError, Option<Bytes>)>> {
+        if let Some(err) = self.err.take() {
+            return ready(Err((err, None))).boxed_local();
+        }
+
+        let limit = self.limit;
+        let wire_limit = self.wire_limit();
+
+        // content length is the size on the wire, so for compressed payloads check it against the
+        // compressed limit
+        let length = self.length.take();
+        if let Some(len) = length {
+            match wire_limit {
+                #[cfg(feature = "compress")]
+                Some(wire_limit) if len > wire_limit => {
+                    return ready(Err((CborPayloadError::CompressedOverflow, None))).boxed_local();
+                }
+                None if len > limit => {
+                    return ready(Err((CborPayloadError::Overflow, None))).boxed_local();
+                }
+                _ => {}
+            }
+        }
+
+        let sequence = self.sequence;
+        let validator = if self.incremental {
+            let validator = Validator::new(limit, self.max_depth);
+            Some(if sequence { validator.sequence() } else { validator })
+        } else {
+            None
+        };
+
+        // the decompressed size is unknown up front
+        let capacity = match wire_limit {
+            None => initial_capacity(length, limit),
+            Some(_) => initial_capacity(None, limit),
+        };
+        let pool = self.pool.take();
+        let buf = match pool {
+            Some(ref pool) => pool.get(capacity),
+            None => BytesMut::with_capacity(capacity),
+        };
+
+        let stream = self.stream.take().unwrap();
+        #[cfg(feature = "compress")]
+            let stream: PayloadStream = {
+            let stream = WireLimit::new(stream, wire_limit);
+            match self.zstd.take() {
+                Some(dictionary) => match ZstdDecoder::new(stream, dictionary) {
+                    Ok(stream) => Box::pin(stream),
+                    Err(e) => {
+                        return ready(Err((PayloadError::Io(e).into(), None))).boxed_local();
+                    }
+                },
+                None => Box::pin(Decompress::new(stream, self.encoding)),
+            }
+        };
+
+        async move {
+            let body = read_body(stream, buf, limit, validator).await.map_err(|e| match e {
+                #[cfg(feature = "compress")]
+                CborPayloadError::Payload(PayloadError::Overflow) => {
+                    (CborPayloadError::CompressedOverflow, None)
+                }
+                e => (e, None),
+            })?;
+
+            let res = if sequence {
+                decode_sequence(&body)
+            } else {
+                serde_cbor::from_slice::<U>(&body)
+            };
+
+            match res {
+                Ok(data) => {
+                    if let Some(pool) = pool {
+                        pool.put(body);
+                    }
+                    Ok(data)
+                }
+                Err(e) => Err((e.into(), Some(body.freeze()))),
+            }
+        }
+            .boxed_local()
+    }
+
+    /// Limit on the size of the payload on the wire, if it is compressed.
+    #[cfg(feature = "compress")]
+    fn wire_limit(&self) -> Option<usize> {
+        if self.encoding == ContentEncoding::Identity && self.zstd.is_none() {
+            None
+        } else {
+            Some(self.compressed_limit.unwrap_or(self.limit))
+        }
+    }
+
+    /// Limit on the size of the payload on the wire, if it is compressed.
+    #[cfg(not(feature = "compress"))]
+    fn wire_limit(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Decode the items of a CBOR sequence as an indefinite length array.
+fn decode_sequence<U: DeserializeOwned>(body: &[u8]) -> serde_cbor::Result<U> {
+    let mut array = Vec::with_capacity(body.len() + 2);
+    array.push(0x9f);
+    array.extend_from_slice(body);
+    array.push(0xff);
+
+    serde_cbor::from_slice(&array)
 }
 
 impl<U> Future for CborBody<U>
@@ -99,35 +362,55 @@ impl<U> Future for CborBody<U>
             return Pin::new(fut).poll(cx);
         }
 
-        if let Some(err) = self.err.take() {
-            return Poll::Ready(Err(err));
-        }
-
-        let limit = self.limit;
-        if let Some(len) = self.length.take() {
-            if len > limit {
-                return Poll::Ready(Err(CborPayloadError::Overflow));
-            }
-        }
-        let mut stream = self.stream.take().unwrap();
-
         self.fut = Some(
-            async move {
-                let mut body = BytesMut::with_capacity(8192);
-
-                while let Some(item) = stream.next().await {
-                    let chunk = item?;
-                    if (body.len() + chunk.len()) > limit {
-                        return Err(CborPayloadError::Overflow);
-                    } else {
-                        body.extend_from_slice(&chunk);
-                    }
-                }
-                Ok(serde_cbor::from_slice::<U>(&body)?)
-            }
+            self.raw_future()
+                .map(|res| res.map_err(|(e, _)| e))
                 .boxed_local(),
         );
 
         self.poll(cx)
     }
-}
\ No newline at end of file
+}
+
+/// Payload stream that fails with `PayloadError::Overflow` once more than `limit` bytes were
+/// received.
+///
+/// Used to limit the compressed size of a payload, before it is decompressed.
+#[cfg(feature = "compress")]
+pub(crate) struct WireLimit<S> {
+    stream: S,
+    limit: Option<usize>,
+    read: usize,
+}
+
+#[cfg(feature = "compress")]
+impl<S> WireLimit<S> {
+    pub(crate) fn new(stream: S, limit: Option<usize>) -> Self {
+        WireLimit {
+            stream,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<S> Stream for WireLimit<S>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+            Some(Ok(chunk)) => chunk,
+            other => return Poll::Ready(other),
+        };
+
+        self.read += chunk.len();
+        match self.limit {
+            Some(limit) if self.read > limit => Poll::Ready(Some(Err(PayloadError::Overflow))),
+            _ => Poll::Ready(Some(Ok(chunk))),
+        }
+    }
+}
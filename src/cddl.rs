@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_http::Response;
+use actix_http::http::StatusCode;
+use actix_web::{HttpRequest, Responder};
+use futures_util::future::{ok, Ready};
+
+/// Types with a [CDDL](https://tools.ietf.org/html/rfc8610) description of their CBOR encoding.
+///
+/// Usually derived with `#[derive(CddlSchema)]`, which follows the same `#[serde(...)]`
+/// attributes as `Serialize` and `Deserialize`: `rename`, `rename_all`, `tag`, `content`,
+/// `untagged`, `transparent`, `flatten`, `skip`, `default` and `skip_serializing_if`. Structs and
+/// enums become named rules, generic ones a rule per instantiation, e.g. `Page-User` for
+/// `Page<User>`.
+///
+/// The CDDL describes the encoding of `Cbor<T>`, which encodes structs as maps keyed by field name
+/// and enums as externally tagged unless configured otherwise.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{CddlGenerator, CddlSchema};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize, CddlSchema)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Reading {
+///     sensor_id: u32,
+///     #[serde(default)]
+///     value: Option<f64>,
+/// }
+///
+/// let mut generator = CddlGenerator::new();
+/// generator.add::<Reading>();
+/// assert_eq!(
+///     generator.to_string(),
+///     "Reading = { \"sensorId\": uint, ? \"value\": float / null }\n",
+/// );
+/// ```
+pub trait CddlSchema {
+    /// CDDL type of `Self`, either inline or the name of a rule added to `generator`.
+    fn cddl_type(generator: &mut CddlGenerator) -> String;
+}
+
+/// Collects the CDDL rules of a set of types.
+///
+/// Each rule is generated once and listed in the order it is first referenced, so the rules of
+/// the types added come first. Rules are named after their type without its module path, and
+/// types of different modules with the same name get a numbered rule, e.g. `Error` and `Error-2`.
+#[derive(Debug, Clone, Default)]
+pub struct CddlGenerator {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Full name of the type, as given by `std::any::type_name`.
+    ty: &'static str,
+    name: String,
+    definition: Option<String>,
+}
+
+impl CddlGenerator {
+    /// Create an empty generator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the rules of `T`, returning its CDDL type.
+    pub fn add<T: CddlSchema + ?Sized>(&mut self) -> String {
+        T::cddl_type(self)
+    }
+
+    /// Define the rule of `T` with the type returned by `define`, unless it was already defined,
+    /// returning the name of the rule.
+    ///
+    /// The rule is registered before `define` runs, so recursive types refer to it by name.
+    pub fn rule<T: ?Sized>(&mut self, define: impl FnOnce(&mut Self) -> String) -> String {
+        let ty = std::any::type_name::<T>();
+        if let Some(rule) = self.rules.iter().find(|rule| rule.ty == ty) {
+            return rule.name.clone();
+        }
+
+        let base = type_rule_name(ty);
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.rules.iter().any(|rule| rule.name == name) {
+            n += 1;
+            name = format!("{}-{}", base, n);
+        }
+
+        let index = self.rules.len();
+        self.rules.push(Rule { ty, name: name.clone(), definition: None });
+        let definition = define(self);
+        self.rules[index].definition = Some(definition);
+
+        name
+    }
+
+    /// Group entries of a flattened field of type `ty`.
+    #[doc(hidden)]
+    pub fn flatten(ty: String) -> String {
+        match ty.strip_prefix('{').and_then(|ty| ty.strip_suffix('}')) {
+            Some(group) => group.trim().to_owned(),
+            None => format!("~{}", ty),
+        }
+    }
+}
+
+impl fmt::Display for CddlGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_rules(f, 0)
+    }
+}
+
+impl CddlGenerator {
+    /// Write the rules from the `start`th on.
+    fn write_rules(&self, f: &mut impl fmt::Write, start: usize) -> fmt::Result {
+        for rule in &self.rules[start..] {
+            writeln!(f, "{} = {}", rule.name, rule.definition.as_deref().unwrap_or("any"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Combined CDDL of the CBOR request and response bodies of an app's endpoints.
+///
+/// Each body gets a rule named after its route, e.g. `post-users-id-request` for the request of
+/// `POST /users/{id}`, followed by the rules of the types it uses that earlier bodies did not.
+/// The spec can be served as is from a route, with the `application/cddl` content type.
+///
+/// The bodies are listed by hand rather than collected from the app, so the spec does not follow
+/// changes to its routes. Keep them next to where the routes are registered.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{CddlSchema, CddlSpec};
+/// use actix_web::{web, App};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, CddlSchema)]
+/// struct User {
+///     name: String,
+/// }
+///
+/// #[derive(Serialize, CddlSchema)]
+/// struct Greeting {
+///     inner: String,
+/// }
+///
+/// let spec = CddlSpec::new()
+///     .request::<User>("POST /users/hello")
+///     .response::<Greeting>("POST /users/hello");
+///
+/// assert!(spec.to_string().contains("post-users-hello-request = User\n"));
+///
+/// let app = App::new().route("/spec.cddl", web::get().to(move || {
+///     let spec = spec.clone();
+///     async move { spec }
+/// }));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CddlSpec {
+    generator: CddlGenerator,
+    cddl: String,
+}
+
+impl CddlSpec {
+    /// Create an empty spec.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the request body of `route`, given as the method and path.
+    pub fn request<T: CddlSchema + ?Sized>(self, route: &str) -> Self {
+        self.body::<T>(route, "request")
+    }
+
+    /// Add the response body of `route`, given as the method and path.
+    pub fn response<T: CddlSchema + ?Sized>(self, route: &str) -> Self {
+        self.body::<T>(route, "response")
+    }
+
+    fn body<T: CddlSchema + ?Sized>(mut self, route: &str, kind: &str) -> Self {
+        // rules of types already in the spec are not repeated
+        let start = self.generator.rules.len();
+        let ty = self.generator.add::<T>();
+
+        if !self.cddl.is_empty() {
+            self.cddl.push('\n');
+        }
+        self.cddl.push_str(&format!("; {} {}\n", route.trim(), kind));
+        self.cddl.push_str(&format!("{}-{} = {}\n", rule_name(route), kind, ty));
+        // writing to a string can't fail
+        let _ = self.generator.write_rules(&mut self.cddl, start);
+        self
+    }
+}
+
+impl fmt::Display for CddlSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.cddl)
+    }
+}
+
+impl Responder for CddlSpec {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Response, Self::Error>>;
+
+    fn respond_to(self, _: &HttpRequest) -> Self::Future {
+        ok(Response::build(StatusCode::OK)
+            .content_type("application/cddl")
+            .body(self.cddl))
+    }
+}
+
+/// Map of group `entries`, used by `#[derive(CddlSchema)]`.
+#[doc(hidden)]
+pub fn __cddl_map(entries: &[String]) -> String {
+    if entries.is_empty() {
+        "{}".to_owned()
+    } else {
+        format!("{{ {} }}", entries.join(", "))
+    }
+}
+
+/// Rule name for a type, e.g. `Page-User` for `app::Page<app::User>`.
+fn type_rule_name(ty: &str) -> String {
+    // path segments include `{{closure}}` for types declared in closures and async fns
+    let is_segment = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '{' || c == '}';
+
+    let mut name = String::with_capacity(ty.len());
+    for c in ty.chars() {
+        if is_segment(c) {
+            name.push(c);
+        } else if c == ':' && name.ends_with(':') {
+            // drop the module path
+            name.pop();
+            let len = name.trim_end_matches(is_segment).len();
+            name.truncate(len);
+        } else if c == ':' {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+
+    name.trim_end_matches('-').to_owned()
+}
+
+/// Rule name for a route, e.g. `post-users-id` for `POST /users/{id}`.
+fn rule_name(route: &str) -> String {
+    let mut name = String::with_capacity(route.len());
+    for c in route.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+
+    name.trim_end_matches('-').to_owned()
+}
+
+macro_rules! impl_primitive {
+    ($cddl:expr => $($ty:ty),+) => {
+        $(
+            impl CddlSchema for $ty {
+                fn cddl_type(_: &mut CddlGenerator) -> String {
+                    $cddl.to_owned()
+                }
+            }
+        )+
+    };
+}
+
+impl_primitive!("bool" => bool);
+impl_primitive!("uint" => u8, u16, u32, u64, u128, usize);
+impl_primitive!("int" => i8, i16, i32, i64, i128, isize);
+impl_primitive!("float" => f32, f64);
+impl_primitive!("tstr" => str, String, char);
+impl_primitive!("null" => ());
+
+macro_rules! impl_wrapper {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: CddlSchema + ?Sized> CddlSchema for $ty<T> {
+                fn cddl_type(generator: &mut CddlGenerator) -> String {
+                    T::cddl_type(generator)
+                }
+            }
+        )+
+    };
+}
+
+impl_wrapper!(Box, Rc, Arc);
+
+impl<T: CddlSchema + ?Sized> CddlSchema for &T {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        T::cddl_type(generator)
+    }
+}
+
+impl<T: CddlSchema> CddlSchema for Option<T> {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("{} / null", T::cddl_type(generator))
+    }
+}
+
+macro_rules! impl_array {
+    ($($ty:ident),+) => {
+        $(
+            impl<T: CddlSchema> CddlSchema for $ty<T> {
+                fn cddl_type(generator: &mut CddlGenerator) -> String {
+                    format!("[* {}]", T::cddl_type(generator))
+                }
+            }
+        )+
+    };
+}
+
+impl_array!(Vec, VecDeque, BTreeSet);
+
+impl<T: CddlSchema, S> CddlSchema for HashSet<T, S> {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("[* {}]", T::cddl_type(generator))
+    }
+}
+
+impl<T: CddlSchema> CddlSchema for [T] {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("[* {}]", T::cddl_type(generator))
+    }
+}
+
+impl<T: CddlSchema, const N: usize> CddlSchema for [T; N] {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("[{}*{} {}]", N, N, T::cddl_type(generator))
+    }
+}
+
+impl<K: CddlSchema, V: CddlSchema> CddlSchema for BTreeMap<K, V> {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("{{* {} => {}}}", K::cddl_type(generator), V::cddl_type(generator))
+    }
+}
+
+impl<K: CddlSchema, V: CddlSchema, S> CddlSchema for HashMap<K, V, S> {
+    fn cddl_type(generator: &mut CddlGenerator) -> String {
+        format!("{{* {} => {}}}", K::cddl_type(generator), V::cddl_type(generator))
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: CddlSchema),+> CddlSchema for ($($name,)+) {
+            fn cddl_type(generator: &mut CddlGenerator) -> String {
+                let items = [$($name::cddl_type(generator)),+];
+                format!("[{}]", items.join(", "))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
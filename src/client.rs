@@ -0,0 +1,180 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_http::{HttpMessage, Payload};
+use actix_http::error::PayloadError;
+use actix_http::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::web::{Bytes, BytesMut};
+use awc::{ClientRequest, ClientResponse, SendClientRequest};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use futures_util::stream::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{CborError, CborEventStream, CborFraming, CborPayloadError, DEFAULT_LIMIT};
+use crate::body::{initial_capacity, is_cbor_content_type, read_body};
+
+/// Extension trait for sending CBOR encoded bodies with `awc`.
+///
+/// # Example
+/// ```no_run
+/// use actix_cbor::CborClientRequestExt;
+///
+/// # async fn send() {
+/// let client = awc::Client::new();
+/// let res = client.post("http://localhost:8080/users/hello")
+///     .send_cbor(&"ferris")
+///     .await;
+/// # }
+/// ```
+pub trait CborClientRequestExt {
+    /// Set a CBOR body and send the request.
+    ///
+    /// The content type is set to `application/cbor` unless one was already set.
+    fn send_cbor<T: Serialize>(self, value: &T) -> SendClientRequest;
+}
+
+impl CborClientRequestExt for ClientRequest {
+    fn send_cbor<T: Serialize>(self, value: &T) -> SendClientRequest {
+        let body = match serde_cbor::to_vec(value) {
+            Ok(body) => body,
+            Err(e) => return actix_web::Error::from(CborError::from(e)).into(),
+        };
+
+        self.set_header_if_none(CONTENT_TYPE, "application/cbor")
+            .send_body(body)
+    }
+}
+
+/// Extension trait for reading CBOR encoded bodies from `awc` responses.
+pub trait CborClientResponseExt<S> {
+    /// Load the response body and decode it as CBOR.
+    ///
+    /// Returns an error if the content type is not `application/cbor`.
+    fn cbor<T: DeserializeOwned + 'static>(&mut self) -> ClientCborBody<S, T>;
+
+    /// Decode the body as the events pushed by a [`CborEvents`](struct.CborEvents.html) response.
+    ///
+    /// The framing is picked from the content type, which must be `application/cbor-seq` or
+    /// `application/x-cbor-frames`.
+    fn cbor_events<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<CborEventStream<Payload<S>, T>, CborPayloadError>;
+}
+
+impl<S> CborClientResponseExt<S> for ClientResponse<S>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin + 'static,
+{
+    fn cbor<T: DeserializeOwned + 'static>(&mut self) -> ClientCborBody<S, T> {
+        ClientCborBody::new(self)
+    }
+
+    fn cbor_events<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<CborEventStream<Payload<S>, T>, CborPayloadError> {
+        let framing = self
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(CborFraming::from_content_type)
+            .ok_or(CborPayloadError::ContentType)?;
+
+        Ok(CborEventStream::new(self.take_payload(), framing))
+    }
+}
+
+/// Client response cbor parser, it resolves to a deserialized `T` value.
+///
+/// This is the client-side counterpart of [`CborBody`](struct.CborBody.html) and fails in the same
+/// way:
+///
+/// * content type is not `application/cbor`
+/// * content length is greater than [`DEFAULT_LIMIT`](constant.DEFAULT_LIMIT.html) (32kB), unless
+///   changed with [`limit`](#method.limit)
+pub struct ClientCborBody<S, U> {
+    limit: usize,
+    length: Option<usize>,
+    stream: Option<Payload<S>>,
+    err: Option<CborPayloadError>,
+    fut: Option<LocalBoxFuture<'static, Result<U, CborPayloadError>>>,
+}
+
+impl<S, U> ClientCborBody<S, U>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin + 'static,
+        U: DeserializeOwned + 'static,
+{
+    /// Create `ClientCborBody` for a client response.
+    pub fn new(res: &mut ClientResponse<S>) -> Self {
+        // check content-type
+        if !is_cbor_content_type(res.content_type(), None) {
+            return ClientCborBody {
+                limit: DEFAULT_LIMIT,
+                length: None,
+                stream: None,
+                fut: None,
+                err: Some(CborPayloadError::ContentType),
+            };
+        }
+
+        let len = res
+            .headers()
+            .get(&CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|s| s.parse::<usize>().ok());
+
+        ClientCborBody {
+            limit: DEFAULT_LIMIT,
+            length: len,
+            stream: Some(res.take_payload()),
+            fut: None,
+            err: None,
+        }
+    }
+
+    /// Change max size of payload. By default max size is 32Kb
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+impl<S, U> Future for ClientCborBody<S, U>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin + 'static,
+        U: DeserializeOwned + 'static,
+{
+    type Output = Result<U, CborPayloadError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(ref mut fut) = self.fut {
+            return Pin::new(fut).poll(cx);
+        }
+
+        if let Some(err) = self.err.take() {
+            return Poll::Ready(Err(err));
+        }
+
+        let limit = self.limit;
+        let length = self.length.take();
+        if let Some(len) = length {
+            if len > limit {
+                return Poll::Ready(Err(CborPayloadError::Overflow));
+            }
+        }
+        let stream = self.stream.take().unwrap();
+
+        self.fut = Some(
+            async move {
+                let buf = BytesMut::with_capacity(initial_capacity(length, limit));
+                let body = read_body(stream, buf, limit, None).await?;
+                Ok(serde_cbor::from_slice::<U>(&body)?)
+            }
+                .boxed_local(),
+        );
+
+        self.poll(cx)
+    }
+}
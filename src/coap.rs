@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use actix_http::http::HeaderName;
+use actix_web::{HttpRequest, web};
+
+use crate::body::is_cbor_content_type;
+use crate::config::ContentTypePredicate;
+
+/// Default header carrying the CoAP Content-Format number of a payload.
+pub const DEFAULT_CONTENT_FORMAT_HEADER: &str = "coap-content-format";
+
+/// CBOR based Content-Formats from the
+/// [IANA CoAP registry](https://www.iana.org/assignments/core-parameters/core-parameters.xhtml#content-formats).
+const REGISTRY: &[(u16, &str)] = &[
+    (16, "application/cose; cose-type=\"cose-encrypt0\""),
+    (17, "application/cose; cose-type=\"cose-mac0\""),
+    (18, "application/cose; cose-type=\"cose-sign1\""),
+    (60, "application/cbor"),
+    (61, "application/cwt"),
+    (63, "application/cbor-seq"),
+    (96, "application/cose; cose-type=\"cose-encrypt\""),
+    (97, "application/cose; cose-type=\"cose-mac\""),
+    (98, "application/cose; cose-type=\"cose-sign\""),
+    (101, "application/cose-key"),
+    (102, "application/cose-key-set"),
+    (112, "application/senml+cbor"),
+    (113, "application/sensml+cbor"),
+    (140, "application/senml-etch+cbor"),
+];
+
+/// Mapping of CoAP Content-Format numbers to CBOR based media types, for requests translated
+/// from CoAP by a proxy.
+///
+/// When registered as app data, [`CborBody`](struct.CborBody.html) and the `Cbor<T>` extractor
+/// take the media type of a payload from the Content-Format number in the
+/// [`coap-content-format`](constant.DEFAULT_CONTENT_FORMAT_HEADER.html) header instead of the
+/// `Content-Type`, if the header is present. The media type of the format is checked like a
+/// `Content-Type`, except that CBOR sequences and types with the `+cbor` suffix are accepted as
+/// well. Other formats, and numbers that are not in the mapping, fail with
+/// [`CborPayloadError::ContentType`](enum.CborPayloadError.html#variant.ContentType).
+///
+/// Payloads in the `application/cbor-seq` format (63) are decoded as an array of their items, so
+/// they can be extracted as a `Vec<T>`. COSE messages and CWTs (16-18, 61, 96-98) are only
+/// accepted by extractors that verify them, such as `CoseMac0<T>` for `application/cose`, and
+/// COSE keys (101, 102) only if the
+/// [`content_type_raw`](struct.CborConfig.html#method.content_type_raw) predicate accepts them.
+///
+/// The mapping starts out with the CBOR based formats of the IANA registry: COSE (16-18, 96-98,
+/// 101, 102), CBOR (60), CWT (61), CBOR sequences (63) and SenML (112, 113, 140).
+///
+/// # Example
+/// ```
+/// use actix_cbor::CoapContentFormats;
+/// use actix_web::App;
+///
+/// let formats = CoapContentFormats::new().format(65000, "application/vnd.example.telemetry+cbor");
+/// let app = App::new().app_data(formats);
+/// ```
+#[derive(Clone)]
+pub struct CoapContentFormats {
+    formats: HashMap<u16, String>,
+    header: HeaderName,
+}
+
+impl Default for CoapContentFormats {
+    fn default() -> Self {
+        CoapContentFormats {
+            formats: REGISTRY
+                .iter()
+                .map(|&(format, media_type)| (format, media_type.to_owned()))
+                .collect(),
+            header: HeaderName::from_static(DEFAULT_CONTENT_FORMAT_HEADER),
+        }
+    }
+}
+
+impl CoapContentFormats {
+    /// Create a mapping of the CBOR based formats in the IANA registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the media type of a Content-Format, e.g. one from the experimental range.
+    /// The media type must be CBOR based.
+    pub fn format(mut self, format: u16, media_type: impl Into<String>) -> Self {
+        self.formats.insert(format, media_type.into());
+        self
+    }
+
+    /// Change the header carrying the Content-Format. By default this is
+    /// [`coap-content-format`](constant.DEFAULT_CONTENT_FORMAT_HEADER.html).
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Media type of a Content-Format, if it is in the mapping.
+    pub fn media_type(&self, format: u16) -> Option<&str> {
+        self.formats.get(&format).map(String::as_str)
+    }
+
+    /// Content-Format of a media type, if it is in the mapping.
+    pub fn content_format(&self, media_type: &str) -> Option<u16> {
+        self.formats
+            .iter()
+            .find(|(_, candidate)| candidate.eq_ignore_ascii_case(media_type))
+            .map(|(&format, _)| format)
+    }
+
+    /// Media type named in the request header, `Ok(None)` if there is no header and `Err(())` if
+    /// the format is invalid or unknown.
+    pub(crate) fn select(&self, req: &HttpRequest) -> Result<Option<&str>, ()> {
+        let format = match req.headers().get(&self.header) {
+            Some(format) => format.to_str().map_err(|_| ())?,
+            None => return Ok(None),
+        };
+
+        let format = format.trim().parse::<u16>().map_err(|_| ())?;
+        self.media_type(format).map(Some).ok_or(())
+    }
+
+    /// Extract the mapping from app data. Check both `CoapContentFormats` and
+    /// `Data<CoapContentFormats>`, in that order.
+    pub(crate) fn from_req(req: &HttpRequest) -> Option<&Self> {
+        req.app_data::<Self>()
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+    }
+}
+
+impl fmt::Debug for CoapContentFormats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut formats = self.formats.keys().collect::<Vec<_>>();
+        formats.sort();
+
+        f.debug_struct("CoapContentFormats")
+            .field("formats", &formats)
+            .field("header", &self.header)
+            .finish()
+    }
+}
+
+/// Media type without its parameters, in lowercase.
+fn essence(media_type: &str) -> String {
+    media_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Whether a media type is a CBOR sequence, whose items are decoded as an array.
+pub(crate) fn is_sequence(media_type: &str) -> bool {
+    let essence = essence(media_type);
+    essence == "application/cbor-seq" || essence.ends_with("+cbor-seq")
+}
+
+/// Check the media type of a Content-Format like a content type, also accepting CBOR sequences
+/// and types with the `+cbor` suffix.
+pub(crate) fn is_cbor_media_type(media_type: &str, ctype: Option<&ContentTypePredicate>) -> bool {
+    let essence = essence(media_type);
+    is_cbor_content_type(&essence, ctype) || essence.ends_with("+cbor") || is_sequence(&essence)
+}
@@ -1,22 +1,73 @@
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::future::Future;
 use std::sync::Arc;
 
+use actix_http::http::{HeaderName, StatusCode};
 use actix_web::{HttpRequest, web};
+use actix_web::web::Bytes;
+use futures_util::future::{FutureExt, LocalBoxFuture, ready};
 
 use crate::CborPayloadError;
+use crate::validate::DEFAULT_MAX_DEPTH;
+
+/// Default maximum size of a CBOR payload, 2^15 bytes (~32kB).
+///
+/// Used by [`CborConfig`](struct.CborConfig.html), [`CborBody`](struct.CborBody.html) and the
+/// other extractors in this crate unless a different limit is configured.
+pub const DEFAULT_LIMIT: usize = 32_768;
 
 // Allow shared refs to default.
-const DEFAULT_CONFIG: CborConfig = CborConfig {
-    limit: 32_768, // 2^15 bytes, (~32kB)
+static DEFAULT_CONFIG: CborConfig = CborConfig {
+    limit: DEFAULT_LIMIT,
+    #[cfg(feature = "compress")]
+    compressed_limit: None,
+    incremental: false,
+    max_depth: DEFAULT_MAX_DEPTH,
     err_handler: None,
+    async_err_handler: None,
     content_type: None,
+    response_limit: None,
+    response_limit_status: StatusCode::INTERNAL_SERVER_ERROR,
+    response_limit_header: None,
+    #[cfg(feature = "compress")]
+    compress_min_size: None,
+    etag: false,
+    ranges: false,
+    query_param: None,
+    path_param: None,
+    typed: Vec::new(),
 };
 
+pub(crate) type ErrorHandler =
+Arc<dyn Fn(CborPayloadError, &HttpRequest) -> actix_web::Error + Send + Sync>;
+pub(crate) type AsyncErrorHandler = Arc<
+    dyn Fn(CborPayloadError, HttpRequest, Option<Bytes>) -> LocalBoxFuture<'static, actix_web::Error>
+    + Send + Sync
+>;
+pub(crate) type ContentTypePredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
 #[derive(Clone)]
 pub struct CborConfig {
     pub(crate) limit: usize,
-    pub(crate) err_handler: Option<Arc<dyn Fn(CborPayloadError, &HttpRequest) -> actix_web::Error
-    + Send + Sync>>,
-    pub(crate) content_type: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+    #[cfg(feature = "compress")]
+    pub(crate) compressed_limit: Option<usize>,
+    pub(crate) incremental: bool,
+    pub(crate) max_depth: usize,
+    pub(crate) err_handler: Option<ErrorHandler>,
+    pub(crate) async_err_handler: Option<AsyncErrorHandler>,
+    pub(crate) content_type: Option<ContentTypePredicate>,
+    pub(crate) response_limit: Option<usize>,
+    pub(crate) response_limit_status: StatusCode,
+    pub(crate) response_limit_header: Option<HeaderName>,
+    #[cfg(feature = "compress")]
+    pub(crate) compress_min_size: Option<usize>,
+    pub(crate) etag: bool,
+    pub(crate) ranges: bool,
+    pub(crate) query_param: Option<String>,
+    pub(crate) path_param: Option<String>,
+    /// Configs of extractors of a specific type, registered at the same level as this one
+    typed: Vec<(TypeId, CborConfig)>,
 }
 
 impl Default for CborConfig {
@@ -27,17 +78,82 @@ impl Default for CborConfig {
 
 impl CborConfig {
     /// Change max size of payload. By default max size is 32Kb
+    ///
+    /// With the `compress` feature this limits the decompressed size of the payload.
     pub fn limit(mut self, limit: usize) -> Self {
         self.limit = limit;
         self
     }
 
+    /// Change max size of the compressed payload as received on the wire. By default this is the
+    /// same as [`limit`](#method.limit).
+    ///
+    /// Payloads with a `Content-Encoding` are checked against both limits, so that small payloads
+    /// which decompress to a large size are rejected.
+    #[cfg(feature = "compress")]
+    pub fn compressed_limit(mut self, limit: usize) -> Self {
+        self.compressed_limit = Some(limit);
+        self
+    }
+
+    /// Validate the structure of the payload as it arrives, instead of only decoding it once it
+    /// was read in full. Disabled by default.
+    ///
+    /// Malformed items, nesting deeper than [`max_depth`](#method.max_depth) and declared lengths
+    /// that can't fit in the limit fail with
+    /// [`CborPayloadError::Malformed`](enum.CborPayloadError.html#variant.Malformed) at the first
+    /// offending byte, without reading the rest of the payload.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Change max nesting depth of arrays, maps and tags checked in
+    /// [`incremental`](#method.incremental) mode. By default max depth is 128.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
     /// Set custom error handler
+    ///
+    /// Replaces any handler set with [`error_handler_async`](#method.error_handler_async).
     pub fn error_handler<F>(mut self, f: F) -> Self
         where
             F: Fn(CborPayloadError, &HttpRequest) -> actix_web::Error + Send + Sync + 'static,
     {
         self.err_handler = Some(Arc::new(f));
+        self.async_err_handler = None;
+        self
+    }
+
+    /// Set custom asynchronous error handler
+    ///
+    /// Besides the error and the request, the handler receives the raw body when it was read in
+    /// full before decoding failed, e.g. to store rejected payloads for later analysis. The body is
+    /// `None` for errors raised before or while reading it, such as content type or size errors.
+    ///
+    /// Replaces any handler set with [`error_handler`](#method.error_handler).
+    ///
+    /// # Example
+    /// ```
+    /// use actix_cbor::CborConfig;
+    /// use actix_web::error::ErrorBadRequest;
+    ///
+    /// let config = CborConfig::default().error_handler_async(|err, req, body| async move {
+    ///     if let Some(body) = body {
+    ///         log::warn!("rejected {} bytes sent to {}", body.len(), req.path());
+    ///     }
+    ///     ErrorBadRequest(err)
+    /// });
+    /// ```
+    pub fn error_handler_async<F, Fut>(mut self, f: F) -> Self
+        where
+            F: Fn(CborPayloadError, HttpRequest, Option<Bytes>) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output=actix_web::Error> + 'static,
+    {
+        self.async_err_handler = Some(Arc::new(move |err, req, body| f(err, req, body).boxed_local()));
+        self.err_handler = None;
         self
     }
 
@@ -50,11 +166,220 @@ impl CborConfig {
         self
     }
 
-    /// Extract payload config from app data. Check both `T` and `Data<T>`, in that order, and fall
-    /// back to the default payload config.
-    pub(crate) fn from_req(req: &HttpRequest) -> &Self {
+    /// Change the query parameter read by [`CborQuery`](struct.CborQuery.html). By default this is
+    /// `cbor`.
+    pub fn query_param(mut self, name: impl Into<String>) -> Self {
+        self.query_param = Some(name.into());
+        self
+    }
+
+    /// Set the path segment read by [`CborPath`](struct.CborPath.html). By default the first
+    /// dynamic segment of the route is read.
+    pub fn path_param(mut self, name: impl Into<String>) -> Self {
+        self.path_param = Some(name.into());
+        self
+    }
+
+    /// Set max size of encoded `Cbor<T>` responses. By default there is no limit.
+    ///
+    /// Responses that encode to more than this fail with
+    /// [`CborResponseError::Overflow`](enum.CborResponseError.html#variant.Overflow) instead of
+    /// being sent.
+    pub fn response_limit(mut self, limit: usize) -> Self {
+        self.response_limit = Some(limit);
+        self
+    }
+
+    /// Set the status code returned for responses over the response limit. By default this is
+    /// `500 Internal Server Error`; `507 Insufficient Storage` is a common alternative.
+    pub fn response_limit_status(mut self, status: StatusCode) -> Self {
+        self.response_limit_status = status;
+        self
+    }
+
+    /// Set a request header through which clients can advertise the largest response they accept,
+    /// as a number of bytes.
+    ///
+    /// The effective limit is the smaller of the advertised size and the
+    /// [`response_limit`](#method.response_limit). Malformed values are ignored.
+    pub fn response_limit_header(mut self, name: HeaderName) -> Self {
+        self.response_limit_header = Some(name);
+        self
+    }
+
+    /// Compress `Cbor<T>` responses of at least `min_size` bytes with the best encoding the client
+    /// accepts. By default responses are not compressed.
+    ///
+    /// Supports `zstd`, `br` and `gzip` in the `Accept-Encoding` header, preferred in that order
+    /// when accepted equally. The response limit applies to the size before compression. Responses
+    /// compressed this way are left alone by the `Compress` middleware.
+    #[cfg(feature = "compress")]
+    pub fn compress_responses(mut self, min_size: usize) -> Self {
+        self.compress_min_size = Some(min_size);
+        self
+    }
+
+    /// Add a strong `ETag` to `Cbor<T>` responses and answer `GET` and `HEAD` requests with a
+    /// matching `If-None-Match` with `304 Not Modified` and no body. Disabled by default.
+    ///
+    /// With ETags enabled responses are encoded deterministically, with map keys in canonical
+    /// order, so that equal values get the same tag. This takes an extra pass over the value.
+    /// Compressed responses get a different tag per encoding.
+    pub fn etag(mut self, etag: bool) -> Self {
+        self.etag = etag;
+        self
+    }
+
+    /// Serve single byte ranges of `Cbor<T>` responses to `GET` and `HEAD` requests with a `Range`
+    /// header, so that interrupted downloads can be resumed. Disabled by default.
+    ///
+    /// Ranges are over the encoded, and possibly compressed, body. Satisfiable ranges get a
+    /// `206 Partial Content` response and others `416 Range Not Satisfiable`. Requests for several
+    /// ranges get the whole body. An `If-Range` header is honored if it matches the
+    /// [`etag`](#method.etag), so enable both to make sure the parts belong to the same document.
+    pub fn ranges(mut self, ranges: bool) -> Self {
+        self.ranges = ranges;
+        self
+    }
+
+    /// Max size of an encoded response to `req`, taking the client hint header into account.
+    pub(crate) fn response_limit_for(&self, req: &HttpRequest) -> Option<usize> {
+        let hint = self
+            .response_limit_header
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|hint| hint.to_str().ok())
+            .and_then(|hint| hint.trim().parse::<usize>().ok());
+
+        match (self.response_limit, hint) {
+            (Some(limit), Some(hint)) => Some(limit.min(hint)),
+            (limit, hint) => limit.or(hint),
+        }
+    }
+
+    /// Use `config` instead of this one for extractors of `T`, such as `Cbor<T>`.
+    ///
+    /// Configs are resolved like any app data: the config of the innermost resource or scope
+    /// wins, and within it the config for the type of the extractor wins over the config itself.
+    /// Type configs of `config` are ignored.
+    ///
+    /// A config inserted into the request extensions, e.g. from a `wrap_fn` middleware, takes
+    /// precedence over app data and is the intended way to override the config for a single
+    /// request.
+    ///
+    /// # Example
+    /// ```
+    /// use actix_cbor::{Cbor, CborConfig};
+    /// use actix_web::{web, App, HttpMessage};
+    /// use actix_web::dev::Service;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Upload {
+    ///     data: Vec<u8>,
+    /// }
+    ///
+    /// async fn upload(upload: Cbor<Upload>) -> String {
+    ///     upload.data.len().to_string()
+    /// }
+    ///
+    /// let app = App::new()
+    ///     .app_data(
+    ///         CborConfig::default()
+    ///             .for_type::<Upload>(CborConfig::default().limit(50 * 1024 * 1024)),
+    ///     )
+    ///     .service(
+    ///         web::resource("/trusted/upload")
+    ///             .wrap_fn(|req, srv| {
+    ///                 // lift the limit for this request only
+    ///                 req.extensions_mut().insert(CborConfig::default().limit(usize::MAX));
+    ///                 srv.call(req)
+    ///             })
+    ///             .route(web::post().to(upload)),
+    ///     );
+    /// ```
+    pub fn for_type<T: 'static>(mut self, mut config: CborConfig) -> Self {
+        config.typed.clear();
+
+        let id = TypeId::of::<T>();
+        self.typed.retain(|(typed, _)| *typed != id);
+        self.typed.push((id, config));
+        self
+    }
+
+    /// Config of extractors of `T` at the level of this config.
+    fn resolve<T: 'static>(&self) -> &Self {
+        let id = TypeId::of::<T>();
+        self.typed
+            .iter()
+            .find(|(typed, _)| *typed == id)
+            .map_or(self, |(_, config)| config)
+    }
+
+    /// Error handlers of this config, for futures that outlive the request.
+    pub(crate) fn error_handlers(&self) -> ErrorHandlers {
+        ErrorHandlers {
+            err_handler: self.err_handler.clone(),
+            async_err_handler: self.async_err_handler.clone(),
+        }
+    }
+
+    /// Extract config for an untyped extractor or responder, checking the request extensions, then
+    /// app data. The config is only cloned when it comes from the request extensions.
+    pub(crate) fn from_req(req: &HttpRequest) -> Cow<'_, Self> {
+        if let Some(config) = req.extensions().get::<Self>() {
+            return Cow::Owned(config.clone());
+        }
+
+        Cow::Borrowed(Self::from_app_data(req).unwrap_or(&DEFAULT_CONFIG))
+    }
+
+    /// Extract payload config for an extractor of `T`.
+    ///
+    /// The innermost config wins: one in the request extensions, then `CborConfig` or
+    /// `Data<CborConfig>` in app data from the innermost resource outwards, as usual in
+    /// actix-web, then the default config. Within that config, a config registered with
+    /// [`for_type`](#method.for_type) for `T` wins. The config is only cloned when it comes
+    /// from the request extensions.
+    pub(crate) fn from_req_for<T: 'static>(req: &HttpRequest) -> Cow<'_, Self> {
+        if let Some(config) = req.extensions().get::<Self>() {
+            return Cow::Owned(config.resolve::<T>().clone());
+        }
+
+        let config = Self::from_app_data(req).unwrap_or(&DEFAULT_CONFIG);
+        Cow::Borrowed(config.resolve::<T>())
+    }
+
+    /// Extract payload config from app data. Check both `CborConfig` and `Data<CborConfig>`, in
+    /// that order.
+    fn from_app_data(req: &HttpRequest) -> Option<&Self> {
         req.app_data::<Self>()
-            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.as_ref()))
-            .unwrap_or_else(|| &DEFAULT_CONFIG)
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+    }
+}
+
+/// Error handlers of a [`CborConfig`](struct.CborConfig.html), cheap to clone into the futures
+/// of extractors.
+#[derive(Clone)]
+pub(crate) struct ErrorHandlers {
+    err_handler: Option<ErrorHandler>,
+    async_err_handler: Option<AsyncErrorHandler>,
+}
+
+impl ErrorHandlers {
+    /// Turn a payload error into an actix error with the configured error handler, if any.
+    pub(crate) fn handle(
+        &self,
+        err: CborPayloadError,
+        req: &HttpRequest,
+        body: Option<Bytes>,
+    ) -> LocalBoxFuture<'static, actix_web::Error> {
+        if let Some(ref handler) = self.async_err_handler {
+            (*handler)(err, req.clone(), body)
+        } else if let Some(ref handler) = self.err_handler {
+            ready((*handler)(err, req)).boxed_local()
+        } else {
+            ready(err.into()).boxed_local()
+        }
     }
-}
\ No newline at end of file
+}
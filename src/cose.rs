@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use actix_http::{Payload, PayloadStream, Response};
+use actix_web::{FromRequest, HttpRequest, Responder, web};
+use futures_util::future::{err, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_cbor::Value;
+use sha2::Sha256;
+
+use crate::{
+    CborBody, CborConfig, CborPayloadError, CborResponseError, CoseReplayGuard, etag,
+    respond_encoded,
+};
+
+/// Media type of COSE_Mac0 messages.
+pub const COSE_MAC0_CONTENT_TYPE: &str = "application/cose; cose-type=\"cose-mac0\"";
+
+/// CBOR tag of COSE_Mac0 messages.
+const COSE_MAC0_TAG: u64 = 17;
+
+/// Header label of the algorithm.
+const ALG: i128 = 1;
+
+/// Header label of the key ID.
+const KID: i128 = 4;
+
+/// Algorithm ID of HMAC 256/256.
+const HMAC_256_256: i128 = 5;
+
+/// Protected header of produced messages, `{1: 5}`.
+const PROTECTED: &[u8] = &[0xa1, 0x01, 0x05];
+
+/// Source of the HMAC keys of [`CoseMac0`](struct.CoseMac0.html) messages.
+///
+/// Implemented for maps of key IDs to keys, and for closures.
+pub trait CoseKeyResolver: Send + Sync {
+    /// Key for the key ID `kid` of a message, or `None` if the key is unknown.
+    fn resolve(&self, kid: Option<&[u8]>) -> Option<Vec<u8>>;
+}
+
+impl<F> CoseKeyResolver for F
+    where
+        F: Fn(Option<&[u8]>) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn resolve(&self, kid: Option<&[u8]>) -> Option<Vec<u8>> {
+        self(kid)
+    }
+}
+
+impl CoseKeyResolver for HashMap<Vec<u8>, Vec<u8>> {
+    fn resolve(&self, kid: Option<&[u8]>) -> Option<Vec<u8>> {
+        kid.and_then(|kid| self.get(kid)).cloned()
+    }
+}
+
+impl CoseKeyResolver for BTreeMap<Vec<u8>, Vec<u8>> {
+    fn resolve(&self, kid: Option<&[u8]>) -> Option<Vec<u8>> {
+        kid.and_then(|kid| self.get(kid)).cloned()
+    }
+}
+
+/// HMAC keys of [`CoseMac0`](struct.CoseMac0.html) messages, registered as app data.
+///
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+///
+/// use actix_cbor::CoseKeys;
+/// use actix_web::App;
+///
+/// let mut keys = HashMap::new();
+/// keys.insert(b"sensor-1".to_vec(), b"0123456789abcdef0123456789abcdef".to_vec());
+///
+/// let app = App::new().app_data(CoseKeys::new(keys));
+/// ```
+#[derive(Clone)]
+pub struct CoseKeys {
+    resolver: Arc<dyn CoseKeyResolver>,
+}
+
+impl CoseKeys {
+    /// Create key storage looking up keys with `resolver`.
+    pub fn new(resolver: impl CoseKeyResolver + 'static) -> Self {
+        CoseKeys {
+            resolver: Arc::new(resolver),
+        }
+    }
+
+    /// Key for the key ID `kid`, if it is known.
+    pub fn resolve(&self, kid: Option<&[u8]>) -> Option<Vec<u8>> {
+        self.resolver.resolve(kid)
+    }
+
+    /// Extract the keys from app data. Check both `CoseKeys` and `Data<CoseKeys>`, in that
+    /// order.
+    pub(crate) fn from_req(req: &HttpRequest) -> Option<&Self> {
+        req.app_data::<Self>()
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+    }
+}
+
+impl fmt::Debug for CoseKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoseKeys").finish()
+    }
+}
+
+/// Extractor/Responder for CBOR encoded data authenticated with a
+/// [COSE_Mac0](https://tools.ietf.org/html/rfc8152#section-6.2) HMAC 256/256 tag, for devices that
+/// can't afford asymmetric signatures.
+///
+/// The key is looked up by the key ID (`kid`) of the message in the [`CoseKeys`](struct.CoseKeys.html)
+/// registered as app data. As an extractor, the message is buffered like a `Cbor<T>` payload
+/// with the `CborConfig` of `T`, and accepted with the `application/cose` content type as well. A
+/// tag that does not match fails with
+/// [`CborPayloadError::MacMismatch`](enum.CborPayloadError.html#variant.MacMismatch) and an
+/// unknown key with [`CborPayloadError::UnknownKey`](enum.CborPayloadError.html#variant.UnknownKey),
+/// both `401 Unauthorized`.
+///
+/// With a [`CoseReplayGuard`](struct.CoseReplayGuard.html) registered as app data, the nonce and
+/// timestamp claims of the payload are checked as well, to reject replayed messages.
+///
+/// As a responder, the value is encoded like a `Cbor<T>` response and tagged with the key of the
+/// message's key ID, with the `application/cose; cose-type="cose-mac0"` content type.
+///
+/// # Example
+/// ```
+/// use actix_cbor::CoseMac0;
+/// use actix_web::post;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize)]
+/// struct Reading {
+///     value: f64,
+/// }
+///
+/// #[derive(Serialize)]
+/// struct Ack {
+///     accepted: bool,
+/// }
+///
+/// #[post("/readings")]
+/// pub async fn record(reading: CoseMac0<Reading>) -> CoseMac0<Ack> {
+///     let kid = reading.kid().map(<[u8]>::to_vec);
+///     CoseMac0::new(Ack { accepted: reading.value >= 0.0 }).with_kid(kid)
+/// }
+/// ```
+pub struct CoseMac0<T> {
+    value: T,
+    kid: Option<Vec<u8>>,
+}
+
+impl<T> CoseMac0<T> {
+    /// Create a message without a key ID.
+    pub fn new(value: T) -> Self {
+        CoseMac0 { value, kid: None }
+    }
+
+    /// Set the key ID the message is tagged with.
+    pub fn with_kid(mut self, kid: impl Into<Option<Vec<u8>>>) -> Self {
+        self.kid = kid.into();
+        self
+    }
+
+    /// Key ID of the message.
+    pub fn kid(&self) -> Option<&[u8]> {
+        self.kid.as_deref()
+    }
+
+    /// Deconstruct to an inner value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CoseMac0<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CoseMac0<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> fmt::Debug for CoseMac0<T> where T: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CoseMac0 ({:?}): {:?}", self.kid, self.value)
+    }
+}
+
+impl<T> Responder for CoseMac0<T> where T: Serialize {
+    type Error = CborResponseError;
+    type Future = Ready<Result<Response, Self::Error>>;
+
+    fn respond_to(self, req: &HttpRequest) -> Self::Future {
+        let config = CborConfig::from_req(req);
+
+        let key = match CoseKeys::from_req(req).and_then(|keys| keys.resolve(self.kid())) {
+            Some(key) => key,
+            None => return err(CborResponseError::UnknownKey),
+        };
+
+        let payload = if config.etag {
+            etag::to_vec_deterministic(&self.value)
+        } else {
+            serde_cbor::to_vec(&self.value)
+        };
+        match payload.and_then(|payload| encode(payload, self.kid, &key)) {
+            Ok(body) => respond_encoded(body, COSE_MAC0_CONTENT_TYPE, req, &config),
+            Err(e) => err(e.into()),
+        }
+    }
+}
+
+impl<T> FromRequest for CoseMac0<T> where T: DeserializeOwned + 'static {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = CborConfig;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload<PayloadStream>) -> Self::Future {
+        let req2 = req.clone();
+        let config = CborConfig::from_req_for::<T>(req);
+        let keys = CoseKeys::from_req(req).cloned();
+        let guard = CoseReplayGuard::from_req(req).cloned();
+
+        let ctype = config.content_type.clone();
+        let mut body = CborBody::<Value>::new(
+            req,
+            payload,
+            Some(Arc::new(move |mime: &str| {
+                mime.eq_ignore_ascii_case("application/cose")
+                    || ctype.as_ref().is_some_and(|predicate| predicate(mime))
+            })),
+        )
+            .limit(config.limit)
+            .incremental(config.incremental)
+            .max_depth(config.max_depth);
+        #[cfg(feature = "compress")]
+            {
+                body.compressed_limit = config.compressed_limit;
+            }
+        let body = body.raw_future();
+        let handlers = config.error_handlers();
+
+        async move {
+            let message = match body.await {
+                Ok(message) => decode(message, keys.as_ref(), guard.as_ref()),
+                Err(e) => Err(e),
+            };
+
+            match message {
+                Err((e, raw)) => {
+                    log::debug!(
+                        "Failed to verify COSE_Mac0 message from payload. \
+                         Request path: {}",
+                        req2.path()
+                    );
+
+                    Err(handlers.handle(e, &req2, raw).await)
+                }
+                Ok(message) => Ok(message),
+            }
+        }
+            .boxed_local()
+    }
+}
+
+/// Verify a COSE_Mac0 `message` and deserialize its payload.
+fn decode<T: DeserializeOwned>(
+    message: Value,
+    keys: Option<&CoseKeys>,
+    guard: Option<&CoseReplayGuard>,
+) -> Result<CoseMac0<T>, (CborPayloadError, Option<web::Bytes>)> {
+    let message = match message {
+        Value::Tag(COSE_MAC0_TAG, message) => *message,
+        message => message,
+    };
+
+    let (protected, unprotected, payload, tag) = match message {
+        Value::Array(items) => match <[Value; 4]>::try_from(items) {
+            Ok([
+                Value::Bytes(protected),
+                Value::Map(unprotected),
+                Value::Bytes(payload),
+                Value::Bytes(tag),
+            ]) => (protected, unprotected, payload, tag),
+            _ => return Err((CborPayloadError::Cose, None)),
+        },
+        _ => return Err((CborPayloadError::Cose, None)),
+    };
+
+    let headers = if protected.is_empty() {
+        BTreeMap::new()
+    } else {
+        match serde_cbor::from_slice(&protected) {
+            Ok(Value::Map(headers)) => headers,
+            _ => return Err((CborPayloadError::Cose, None)),
+        }
+    };
+    if headers.get(&Value::Integer(ALG)) != Some(&Value::Integer(HMAC_256_256)) {
+        return Err((CborPayloadError::Cose, None));
+    }
+
+    let kid = match headers
+        .get(&Value::Integer(KID))
+        .or_else(|| unprotected.get(&Value::Integer(KID)))
+    {
+        Some(Value::Bytes(kid)) => Some(kid.clone()),
+        Some(_) => return Err((CborPayloadError::Cose, None)),
+        None => None,
+    };
+
+    let key = match keys.and_then(|keys| keys.resolve(kid.as_deref())) {
+        Some(key) => key,
+        None => return Err((CborPayloadError::UnknownKey, None)),
+    };
+
+    let mac = hmac(&key, &protected, &payload).map_err(|e| (e.into(), None))?;
+    if mac.verify_slice(&tag).is_err() {
+        return Err((CborPayloadError::MacMismatch, None));
+    }
+
+    // claims are only checked, and nonces only remembered, for authenticated messages
+    let claims = match guard {
+        Some(guard) => match serde_cbor::from_slice::<Value>(&payload) {
+            Ok(claims) => {
+                guard.check(kid.as_deref(), &claims).map_err(|e| (e, None))?;
+                Some(claims)
+            }
+            Err(e) => return Err((e.into(), Some(payload.into()))),
+        },
+        None => None,
+    };
+
+    let value = match claims {
+        Some(claims) => serde_cbor::value::from_value(claims),
+        None => serde_cbor::from_slice(&payload),
+    };
+
+    match value {
+        Ok(value) => Ok(CoseMac0 { value, kid }),
+        Err(e) => Err((e.into(), Some(payload.into()))),
+    }
+}
+
+/// Encode an encoded `payload` as a tagged COSE_Mac0 message.
+fn encode(payload: Vec<u8>, kid: Option<Vec<u8>>, key: &[u8]) -> serde_cbor::Result<Vec<u8>> {
+    let tag = hmac(key, PROTECTED, &payload)?.finalize().into_bytes().to_vec();
+
+    let mut unprotected = BTreeMap::new();
+    if let Some(kid) = kid {
+        unprotected.insert(Value::Integer(KID), Value::Bytes(kid));
+    }
+
+    let message = Value::Array(vec![
+        Value::Bytes(PROTECTED.to_vec()),
+        Value::Map(unprotected),
+        Value::Bytes(payload),
+        Value::Bytes(tag),
+    ]);
+
+    // major type 6 with the tag in the additional information
+    let mut body = vec![0xc0 | COSE_MAC0_TAG as u8];
+    serde_cbor::to_writer(&mut body, &message)?;
+    Ok(body)
+}
+
+/// HMAC-SHA256 over the `MAC_structure` of a message, without external data.
+fn hmac(key: &[u8], protected: &[u8], payload: &[u8]) -> serde_cbor::Result<Hmac<Sha256>> {
+    let structure = Value::Array(vec![
+        Value::Text("MAC0".to_owned()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&serde_cbor::to_vec(&structure)?);
+    Ok(mac)
+}
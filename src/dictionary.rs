@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_http::error::PayloadError;
+use actix_http::http::HeaderName;
+use actix_web::{HttpRequest, web};
+use actix_web::web::Bytes;
+use futures_util::ready;
+use futures_util::stream::Stream;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+use zstd::stream::raw::{Decoder, InBuffer, Operation, OutBuffer};
+
+/// Default header carrying the ID of the zstd dictionary a payload was compressed with.
+pub const DEFAULT_DICTIONARY_HEADER: &str = "zstd-dictionary-id";
+
+/// Size of the chunks a zstd payload is decompressed in.
+const OUTPUT_CHUNK: usize = 16_384;
+
+/// Pre-shared zstd dictionaries for `Content-Encoding: zstd` payloads, selected by ID.
+///
+/// When registered as app data, [`CborBody`](struct.CborBody.html) and the `Cbor<T>` extractor
+/// decompress zstd payloads with the dictionary named in the
+/// [`zstd-dictionary-id`](constant.DEFAULT_DICTIONARY_HEADER.html) header. Payloads naming an
+/// unknown dictionary fail with
+/// [`CborPayloadError::UnknownDictionary`](enum.CborPayloadError.html#variant.UnknownDictionary).
+///
+/// Responses compressed with
+/// [`CborConfig::compress_responses`](struct.CborConfig.html#method.compress_responses) use the
+/// same dictionary as the request, if any and zstd is the negotiated encoding. The dictionary ID
+/// header is then set on the response too.
+///
+/// # Example
+/// ```
+/// use actix_cbor::ZstdDictionaries;
+/// use actix_web::App;
+///
+/// # let telemetry_v1: Vec<u8> = Vec::new();
+/// let dictionaries = ZstdDictionaries::new().dictionary("telemetry-v1", telemetry_v1);
+/// let app = App::new().app_data(dictionaries);
+/// ```
+#[derive(Clone)]
+pub struct ZstdDictionaries {
+    dictionaries: HashMap<String, Dictionary>,
+    header: HeaderName,
+}
+
+/// A dictionary prepared for compression and decompression.
+#[derive(Clone)]
+pub(crate) struct Dictionary {
+    id: Arc<str>,
+    encoder: Arc<EncoderDictionary<'static>>,
+    decoder: Arc<DecoderDictionary<'static>>,
+}
+
+impl Default for ZstdDictionaries {
+    fn default() -> Self {
+        ZstdDictionaries {
+            dictionaries: HashMap::new(),
+            header: HeaderName::from_static(DEFAULT_DICTIONARY_HEADER),
+        }
+    }
+}
+
+impl ZstdDictionaries {
+    /// Create an empty set of dictionaries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dictionary, e.g. one trained with `zstd --train`, under `id`.
+    pub fn dictionary(mut self, id: impl Into<String>, dictionary: impl AsRef<[u8]>) -> Self {
+        let id = id.into();
+        let dictionary = Dictionary {
+            id: id.as_str().into(),
+            encoder: Arc::new(EncoderDictionary::copy(
+                dictionary.as_ref(),
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )),
+            decoder: Arc::new(DecoderDictionary::copy(dictionary.as_ref())),
+        };
+
+        self.dictionaries.insert(id, dictionary);
+        self
+    }
+
+    /// Change the header carrying the dictionary ID. By default this is
+    /// [`zstd-dictionary-id`](constant.DEFAULT_DICTIONARY_HEADER.html).
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.header = name;
+        self
+    }
+
+    pub(crate) fn header_name(&self) -> &HeaderName {
+        &self.header
+    }
+
+    /// Dictionary named in the request header, `Ok(None)` if there is no header and `Err(())` if
+    /// the dictionary is unknown.
+    pub(crate) fn select(&self, req: &HttpRequest) -> Result<Option<Dictionary>, ()> {
+        let id = match req.headers().get(&self.header) {
+            Some(id) => id.to_str().map_err(|_| ())?,
+            None => return Ok(None),
+        };
+
+        self.dictionaries.get(id.trim()).cloned().map(Some).ok_or(())
+    }
+
+    /// Extract the dictionaries from app data. Check both `ZstdDictionaries` and
+    /// `Data<ZstdDictionaries>`, in that order.
+    pub(crate) fn from_req(req: &HttpRequest) -> Option<&Self> {
+        req.app_data::<Self>()
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+    }
+}
+
+impl fmt::Debug for ZstdDictionaries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdDictionaries")
+            .field("ids", &self.dictionaries.keys().collect::<Vec<_>>())
+            .field("header", &self.header)
+            .finish()
+    }
+}
+
+impl Dictionary {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Compress `body` with this dictionary.
+    pub(crate) fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)?.compress(body)
+    }
+}
+
+/// Payload stream that decompresses a `Content-Encoding: zstd` payload, optionally with a
+/// dictionary.
+///
+/// Output is produced in chunks of at most 16kB, so that the payload limit is checked while a
+/// chunk is being decompressed rather than after.
+pub(crate) struct ZstdDecoder<S> {
+    // declared before the dictionary it refers to, so that it is dropped first
+    decoder: Decoder<'static>,
+    _dictionary: Option<Dictionary>,
+    stream: S,
+    input: Bytes,
+    /// The output buffer was filled by the last run, so there may be more output pending
+    pending: bool,
+    /// The last run ended a frame
+    complete: bool,
+}
+
+impl<S> ZstdDecoder<S> {
+    pub(crate) fn new(stream: S, dictionary: Option<Dictionary>) -> io::Result<Self> {
+        let decoder = match dictionary {
+            Some(ref dictionary) => Decoder::with_prepared_dictionary(&dictionary.decoder)?,
+            None => Decoder::new()?,
+        };
+
+        Ok(ZstdDecoder {
+            decoder,
+            _dictionary: dictionary,
+            stream,
+            input: Bytes::new(),
+            pending: false,
+            complete: false,
+        })
+    }
+}
+
+impl<S> Stream for ZstdDecoder<S>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            if !this.input.is_empty() || this.pending {
+                let mut output = vec![0; OUTPUT_CHUNK];
+                let mut input = InBuffer::around(&this.input[..]);
+                let mut out = OutBuffer::around(&mut output[..]);
+
+                let hint = match this.decoder.run(&mut input, &mut out) {
+                    Ok(hint) => hint,
+                    Err(_) => return Poll::Ready(Some(Err(PayloadError::EncodingCorrupted))),
+                };
+                let (consumed, written) = (input.pos(), out.pos());
+
+                if consumed == 0 && written == 0 && !this.pending {
+                    return Poll::Ready(Some(Err(PayloadError::EncodingCorrupted)));
+                }
+
+                let _ = this.input.split_to(consumed);
+                this.pending = written == OUTPUT_CHUNK;
+                this.complete = hint == 0;
+
+                if written > 0 {
+                    output.truncate(written);
+                    return Poll::Ready(Some(Ok(Bytes::from(output))));
+                }
+                continue;
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(chunk)) => this.input = chunk,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None if this.complete => return Poll::Ready(None),
+                None => return Poll::Ready(Some(Err(PayloadError::Incomplete(None)))),
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{self, Write};
+
+use actix_http::http::HeaderName;
+use actix_http::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, VARY};
+use actix_http::ResponseBuilder;
+use actix_web::HttpRequest;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::dictionary::Dictionary;
+use crate::ZstdDictionaries;
+
+/// Content encodings supported for `Cbor<T>` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Zstd,
+    Br,
+    Gzip,
+}
+
+impl Encoding {
+    /// Supported encodings, in order of preference when the client accepts several equally.
+    const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Br, Encoding::Gzip];
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Br => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Pick the encoding with the highest quality in the `Accept-Encoding` headers of `req`, if
+    /// any is acceptable.
+    pub(crate) fn negotiate(req: &HttpRequest) -> Option<Encoding> {
+        let mut qualities = [None; 3];
+        let mut wildcard = None;
+
+        let items = req
+            .headers()
+            .get_all(ACCEPT_ENCODING)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for item in items {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap_or("").trim();
+            let quality = match parse_quality(parts) {
+                Some(quality) => quality,
+                None => continue,
+            };
+
+            if name == "*" {
+                wildcard = Some(quality);
+            } else if let Some(i) = Self::ALL
+                .iter()
+                .position(|enc| enc.as_str().eq_ignore_ascii_case(name))
+            {
+                qualities[i] = Some(quality);
+            } else if name.eq_ignore_ascii_case("x-gzip") {
+                qualities[2] = Some(quality);
+            }
+        }
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for (enc, quality) in Self::ALL.iter().zip(qualities.iter()) {
+            let quality = quality.or(wildcard).unwrap_or(0.0);
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((*enc, quality));
+            }
+        }
+
+        best.map(|(enc, _)| enc)
+    }
+
+    /// Compress `body` with this encoding.
+    pub(crate) fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::stream::encode_all(body, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Encoding::Br => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 32 * 1024, 3, 22);
+                encoder.write_all(body)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Parse the `q` parameter of an `Accept-Encoding` item, defaulting to 1. `None` if it is invalid.
+fn parse_quality<'a>(params: impl Iterator<Item=&'a str>) -> Option<f32> {
+    let mut quality = 1.0;
+
+    for param in params {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next().unwrap_or("").trim();
+        if key.eq_ignore_ascii_case("q") {
+            quality = kv.next()?.trim().parse::<f32>().ok()?;
+            if !(0.0..=1.0).contains(&quality) {
+                return None;
+            }
+        }
+    }
+
+    Some(quality)
+}
+
+/// Encoding negotiated for a `Cbor<T>` response, with the zstd dictionary if any.
+pub(crate) struct ResponseEncoding {
+    encoding: Encoding,
+    dictionary: Option<(HeaderName, Dictionary)>,
+}
+
+impl ResponseEncoding {
+    /// Negotiate the encoding of a response body of `len` bytes to `req`. Bodies smaller than
+    /// `min_size` are not compressed.
+    ///
+    /// zstd responses use the dictionary named in the request, if any. Sets the `Vary` header on
+    /// `res`, since the response depends on the request headers whether or not it ends up
+    /// compressed.
+    pub(crate) fn negotiate(
+        req: &HttpRequest,
+        min_size: usize,
+        len: usize,
+        res: &mut ResponseBuilder,
+    ) -> Option<Self> {
+        let dictionaries = ZstdDictionaries::from_req(req);
+        match dictionaries {
+            Some(dictionaries) => {
+                let vary = format!("accept-encoding, {}", dictionaries.header_name().as_str());
+                res.header(VARY, vary)
+            }
+            None => res.header(VARY, "accept-encoding"),
+        };
+
+        if len < min_size {
+            return None;
+        }
+
+        let encoding = Encoding::negotiate(req)?;
+        let dictionary = match (encoding, dictionaries) {
+            (Encoding::Zstd, Some(dictionaries)) => match dictionaries.select(req) {
+                Ok(Some(dictionary)) => Some((dictionaries.header_name().clone(), dictionary)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        Some(ResponseEncoding { encoding, dictionary })
+    }
+
+    /// Name of the encoded representation, including the dictionary.
+    pub(crate) fn variant(&self) -> String {
+        match self.dictionary {
+            Some((_, ref dictionary)) => format!("{};{}", self.encoding.as_str(), dictionary.id()),
+            None => self.encoding.as_str().to_owned(),
+        }
+    }
+
+    /// Compress `body`, setting the `Content-Encoding` on `res`. The body is returned as is if it
+    /// can't be compressed.
+    pub(crate) fn encode(self, res: &mut ResponseBuilder, body: Vec<u8>) -> Vec<u8> {
+        let encoded = match self.dictionary {
+            Some((_, ref dictionary)) => dictionary.compress(&body),
+            None => self.encoding.encode(&body),
+        };
+
+        match encoded {
+            Ok(encoded) => {
+                res.header(CONTENT_ENCODING, self.encoding.as_str());
+                if let Some((header, dictionary)) = self.dictionary {
+                    res.header(header, dictionary.id());
+                }
+                encoded
+            }
+            Err(e) => {
+                log::debug!(
+                    "Failed to compress CBOR response with {}: {}",
+                    self.encoding.as_str(),
+                    e
+                );
+                body
+            }
+        }
+    }
+}
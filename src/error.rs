@@ -5,21 +5,89 @@ use actix_http::error::PayloadError;
 use actix_http::http::StatusCode;
 use actix_http::ResponseError;
 use actix_web::HttpResponse;
-use futures_util::core_reexport::fmt::{Display, Formatter};
+
+use crate::MalformedError;
+#[cfg(feature = "multipart")]
+use crate::MultipartError;
 
 #[derive(Debug)]
 pub struct CborError(serde_cbor::Error);
 
+/// Error decoding the base64url encoding of a CBOR parameter.
+#[derive(Debug)]
+pub struct Base64Error(base64::DecodeError);
+
+/// Errors that can occur while producing a `Cbor<T>` response.
+///
+/// Features can add variants, so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CborResponseError {
+    /// Serialize error
+    Serialize(CborError),
+    /// Encoded response is bigger than allowed
+    Overflow {
+        /// Size of the encoded response
+        size: usize,
+        /// Configured or advertised limit
+        limit: usize,
+        /// Status code to respond with
+        status: StatusCode,
+    },
+    /// No key is known for the key ID of a `CoseMac0<T>` response
+    #[cfg(feature = "cose")]
+    UnknownKey,
+}
+
+/// Errors that can occur while reading a CBOR payload.
+///
+/// Features can add variants, so matches need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CborPayloadError {
     /// Payload size is bigger than allowed. (default: 32kB)
+    ///
+    /// With the `compress` feature this is the size after decompression.
     Overflow,
+    /// Compressed payload size is bigger than allowed. (default: same as the payload limit)
+    #[cfg(feature = "compress")]
+    CompressedOverflow,
+    /// Payload was compressed with a zstd dictionary that is not registered
+    #[cfg(feature = "compress")]
+    UnknownDictionary,
     /// Content type error
     ContentType,
     /// Deserialize error
     Deserialize(CborError),
+    /// Payload was rejected by the incremental decoder before it was read in full
+    Malformed(MalformedError),
+    /// Query parameter, path segment or header is missing
+    MissingParameter,
+    /// Query parameter, path segment or header is not valid base64url
+    Base64(Base64Error),
+    /// Payload is not a COSE_Mac0 message tagged with HMAC 256/256
+    #[cfg(feature = "cose")]
+    Cose,
+    /// No key is known for the key ID of a COSE_Mac0 message
+    #[cfg(feature = "cose")]
+    UnknownKey,
+    /// Tag of a COSE_Mac0 message does not match its payload
+    #[cfg(feature = "cose")]
+    MacMismatch,
+    /// Nonce or timestamp claim of a COSE_Mac0 payload is missing or invalid
+    #[cfg(feature = "cose")]
+    MissingClaim,
+    /// Timestamp claim of a COSE_Mac0 payload is too old, or too far in the future
+    #[cfg(feature = "cose")]
+    Expired,
+    /// Nonce claim of a COSE_Mac0 payload was already seen
+    #[cfg(feature = "cose")]
+    Replayed,
     /// Payload error
     Payload(PayloadError),
+    /// Multipart error
+    #[cfg(feature = "multipart")]
+    Multipart(MultipartError),
 }
 
 impl From<CborError> for CborPayloadError {
@@ -40,30 +108,69 @@ impl From<PayloadError> for CborPayloadError {
     }
 }
 
-impl Display for CborPayloadError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl fmt::Display for CborPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CborPayloadError::Overflow => writeln!(f, "Cbor payload size is bigger than allowed"),
+            #[cfg(feature = "compress")]
+            CborPayloadError::CompressedOverflow => {
+                writeln!(f, "Compressed cbor payload size is bigger than allowed")
+            }
+            #[cfg(feature = "compress")]
+            CborPayloadError::UnknownDictionary => writeln!(f, "Unknown zstd dictionary"),
             CborPayloadError::ContentType => writeln!(f, "Content type error"),
             CborPayloadError::Deserialize(inner) => {
                 writeln!(f, "CBOR deserialize error: {}", inner)
             }
+            CborPayloadError::Malformed(inner) => writeln!(f, "Malformed CBOR payload: {}", inner),
+            CborPayloadError::MissingParameter => writeln!(f, "CBOR parameter is missing"),
+            CborPayloadError::Base64(inner) => {
+                writeln!(f, "CBOR parameter is not valid base64url: {}", inner)
+            }
+            #[cfg(feature = "cose")]
+            CborPayloadError::Cose => writeln!(f, "Payload is not a valid COSE_Mac0 message"),
+            #[cfg(feature = "cose")]
+            CborPayloadError::UnknownKey => writeln!(f, "Unknown COSE key"),
+            #[cfg(feature = "cose")]
+            CborPayloadError::MacMismatch => writeln!(f, "COSE_Mac0 tag does not match"),
+            #[cfg(feature = "cose")]
+            CborPayloadError::MissingClaim => writeln!(f, "Nonce or timestamp claim is missing"),
+            #[cfg(feature = "cose")]
+            CborPayloadError::Expired => writeln!(f, "Timestamp claim is outside the accepted window"),
+            #[cfg(feature = "cose")]
+            CborPayloadError::Replayed => writeln!(f, "Nonce claim was already used"),
             CborPayloadError::Payload(inner) => {
                 writeln!(f, "Error that occur during reading payload: {:?}", inner)
             }
+            #[cfg(feature = "multipart")]
+            CborPayloadError::Multipart(inner) => {
+                writeln!(f, "Error that occur during reading multipart payload: {}", inner)
+            }
         }
     }
 }
 
 impl Error for CborPayloadError {}
 
-/// Return `BadRequest` for `CborPayloadError`
+/// Return `BadRequest` for `CborPayloadError`, `PayloadTooLarge` for overflows and
+/// `Unauthorized` for COSE messages that can't be authenticated
 impl ResponseError for CborPayloadError {
     fn error_response(&self) -> HttpResponse {
         match *self {
             CborPayloadError::Overflow => {
                 HttpResponse::new(StatusCode::PAYLOAD_TOO_LARGE)
             }
+            #[cfg(feature = "compress")]
+            CborPayloadError::CompressedOverflow => {
+                HttpResponse::new(StatusCode::PAYLOAD_TOO_LARGE)
+            }
+            #[cfg(feature = "cose")]
+            CborPayloadError::UnknownKey
+            | CborPayloadError::MacMismatch
+            | CborPayloadError::Expired
+            | CborPayloadError::Replayed => {
+                HttpResponse::new(StatusCode::UNAUTHORIZED)
+            }
             _ => HttpResponse::new(StatusCode::BAD_REQUEST),
         }
     }
@@ -87,4 +194,58 @@ impl From<serde_cbor::Error> for CborError {
     fn from(e: serde_cbor::Error) -> Self {
         Self(e)
     }
-}
\ No newline at end of file
+}
+
+impl Base64Error {
+    pub(crate) fn new(e: base64::DecodeError) -> Self {
+        Self(e)
+    }
+}
+
+impl fmt::Display for Base64Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for Base64Error {}
+
+impl From<CborError> for CborResponseError {
+    fn from(e: CborError) -> Self {
+        Self::Serialize(e)
+    }
+}
+
+impl From<serde_cbor::Error> for CborResponseError {
+    fn from(e: serde_cbor::Error) -> Self {
+        Self::Serialize(e.into())
+    }
+}
+
+impl fmt::Display for CborResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CborResponseError::Serialize(inner) => write!(f, "CBOR serialize error: {}", inner),
+            CborResponseError::Overflow { size, limit, .. } => write!(
+                f,
+                "Cbor response size {} is bigger than allowed ({})",
+                size, limit
+            ),
+            #[cfg(feature = "cose")]
+            CborResponseError::UnknownKey => write!(f, "Unknown COSE key"),
+        }
+    }
+}
+
+impl Error for CborResponseError {}
+
+impl ResponseError for CborResponseError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CborResponseError::Serialize(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CborResponseError::Overflow { status, .. } => *status,
+            #[cfg(feature = "cose")]
+            CborResponseError::UnknownKey => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
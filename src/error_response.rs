@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::fmt;
+
+use actix_http::http::StatusCode;
+use actix_http::ResponseError;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// Handler error returned as a CBOR encoded body.
+///
+/// The body is `error` encoded as CBOR, with the chosen status code and by default the
+/// `application/cbor` content type. If `error` can't be encoded, the response is a bare
+/// `500 Internal Server Error`.
+///
+/// Domain errors implementing [`IntoCborErrorResponse`](trait.IntoCborErrorResponse.html) convert
+/// to it with `?`.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborErrorResponse};
+/// use actix_web::get;
+/// use actix_web::http::StatusCode;
+/// use actix_web::web::Path;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize, Debug)]
+/// struct ApiError {
+///     code: u32,
+///     message: String,
+/// }
+///
+/// #[get("/users/{id}")]
+/// pub async fn user(id: Path<u32>) -> Result<Cbor<String>, CborErrorResponse<ApiError>> {
+///     Err(CborErrorResponse::new(
+///         StatusCode::NOT_FOUND,
+///         ApiError { code: 404, message: format!("No user {}", id) },
+///     ))
+/// }
+/// ```
+pub struct CborErrorResponse<E> {
+    error: E,
+    status: StatusCode,
+    content_type: String,
+}
+
+impl<E> CborErrorResponse<E> {
+    /// Create an error response with `error` as the body.
+    pub fn new(status: StatusCode, error: E) -> Self {
+        CborErrorResponse {
+            error,
+            status,
+            content_type: "application/cbor".to_owned(),
+        }
+    }
+
+    /// Change the content type of the body, e.g. to a `+cbor` problem details type.
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    /// Status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Error encoded in the body.
+    pub fn error(&self) -> &E {
+        &self.error
+    }
+
+    /// Deconstruct to the inner error
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E> fmt::Debug for CborErrorResponse<E> where E: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CborErrorResponse ({}): {:?}", self.status, self.error)
+    }
+}
+
+impl<E> fmt::Display for CborErrorResponse<E> where E: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.status, self.error)
+    }
+}
+
+impl<E> Error for CborErrorResponse<E> where E: fmt::Debug {}
+
+impl<E> ResponseError for CborErrorResponse<E> where E: Serialize + fmt::Debug {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match serde_cbor::to_vec(&self.error) {
+            Ok(body) => HttpResponse::build(self.status)
+                .content_type(self.content_type.as_str())
+                .body(body),
+            Err(e) => {
+                log::error!("Failed to serialize CBOR error response: {}", e);
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Mapping of a domain error to a [`CborErrorResponse`](struct.CborErrorResponse.html), which
+/// lets handlers returning `Result<_, CborErrorResponse<Self::Body>>` use `?` on it.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborErrorResponse, IntoCborErrorResponse};
+/// use actix_web::get;
+/// use actix_web::http::StatusCode;
+/// use actix_web::web::Path;
+/// use serde::Serialize;
+///
+/// #[derive(Debug)]
+/// enum StoreError {
+///     NotFound,
+///     Unavailable,
+/// }
+///
+/// #[derive(Serialize, Debug)]
+/// struct ApiError {
+///     code: u32,
+/// }
+///
+/// impl IntoCborErrorResponse for StoreError {
+///     type Body = ApiError;
+///
+///     fn into_cbor_error_response(self) -> CborErrorResponse<ApiError> {
+///         let (status, code) = match self {
+///             StoreError::NotFound => (StatusCode::NOT_FOUND, 1),
+///             StoreError::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, 2),
+///         };
+///         CborErrorResponse::new(status, ApiError { code })
+///     }
+/// }
+///
+/// fn load(id: u32) -> Result<String, StoreError> {
+///     if id == 0 { Ok("root".to_owned()) } else { Err(StoreError::NotFound) }
+/// }
+///
+/// #[get("/users/{id}")]
+/// pub async fn user(id: Path<u32>) -> Result<Cbor<String>, CborErrorResponse<ApiError>> {
+///     Ok(Cbor(load(*id)?))
+/// }
+/// ```
+pub trait IntoCborErrorResponse {
+    /// Type encoded in the body of the response.
+    type Body: Serialize;
+
+    /// Map the error to a response.
+    fn into_cbor_error_response(self) -> CborErrorResponse<Self::Body>;
+}
+
+impl<E> From<E> for CborErrorResponse<E::Body> where E: IntoCborErrorResponse {
+    fn from(e: E) -> Self {
+        e.into_cbor_error_response()
+    }
+}
@@ -0,0 +1,49 @@
+use actix_http::http::Method;
+use actix_http::http::header::IF_NONE_MATCH;
+use actix_web::HttpRequest;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Encode `value` deterministically, with map keys sorted in canonical CBOR order, so that equal
+/// values always get the same ETag.
+pub(crate) fn to_vec_deterministic<T: Serialize>(value: &T) -> serde_cbor::Result<Vec<u8>> {
+    serde_cbor::to_vec(&serde_cbor::value::to_value(value)?)
+}
+
+/// Strong ETag for an encoded body, distinguished by the `variant` of the representation, such as
+/// its content encoding.
+pub(crate) fn entity_tag(body: &[u8], variant: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(body);
+    if let Some(variant) = variant {
+        hasher.update([0]);
+        hasher.update(variant.as_bytes());
+    }
+
+    let hash = hasher.finalize();
+    let mut tag = String::with_capacity(34);
+    tag.push('"');
+    for b in &hash[..16] {
+        tag.push_str(&format!("{:02x}", b));
+    }
+    tag.push('"');
+    tag
+}
+
+/// Whether a `GET` or `HEAD` request has an `If-None-Match` header matching `tag`.
+///
+/// Uses the weak comparison, as required for `If-None-Match`.
+pub(crate) fn not_modified(req: &HttpRequest, tag: &str) -> bool {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return false;
+    }
+
+    req.headers()
+        .get_all(IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim())
+        .any(|candidate| {
+            candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == tag
+        })
+}
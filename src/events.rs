@@ -0,0 +1,424 @@
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_http::error::PayloadError;
+use actix_http::http::StatusCode;
+use actix_http::http::header::CACHE_CONTROL;
+use actix_http::Response;
+use actix_rt::time::{delay_for, Delay, Instant};
+use actix_web::{HttpRequest, Responder};
+use actix_web::web::{Bytes, BytesMut};
+use futures_channel::mpsc;
+use futures_util::future::{ok, Ready};
+use futures_util::ready;
+use futures_util::stream::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{CborConfig, CborPayloadError, CborResponseError, DEFAULT_LIMIT, MalformedError};
+use crate::validate::{DEFAULT_MAX_DEPTH, Validator};
+
+/// Heartbeat of a CBOR sequence, the `undefined` simple value.
+const SEQUENCE_HEARTBEAT: u8 = 0xf7;
+
+/// How the events of a [`CborEvents`](struct.CborEvents.html) response are delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CborFraming {
+    /// CBOR sequence ([RFC 8742](https://tools.ietf.org/html/rfc8742)) with the content type
+    /// `application/cbor-seq`: items are concatenated as is. Heartbeats are the `undefined`
+    /// simple value (`0xf7`).
+    ///
+    /// Events can't be `undefined` themselves, or they would be skipped as heartbeats. serde_cbor
+    /// never encodes a value as `undefined`, so this only affects sequences produced elsewhere,
+    /// and only their top-level items: `undefined` nested in an event is decoded as usual.
+    Sequence,
+    /// Items prefixed with their length as a 4-byte big-endian integer, with the content type
+    /// `application/x-cbor-frames`. Heartbeats are empty frames.
+    LengthPrefixed,
+}
+
+impl CborFraming {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            CborFraming::Sequence => "application/cbor-seq",
+            CborFraming::LengthPrefixed => "application/x-cbor-frames",
+        }
+    }
+
+    /// Framing of a response with the given content type, if it has one.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        [CborFraming::Sequence, CborFraming::LengthPrefixed]
+            .iter()
+            .copied()
+            .find(|framing| mime.eq_ignore_ascii_case(framing.content_type()))
+    }
+
+    fn frame(self, body: Vec<u8>) -> Bytes {
+        match self {
+            CborFraming::Sequence => Bytes::from(body),
+            CborFraming::LengthPrefixed => {
+                let mut frame = Vec::with_capacity(4 + body.len());
+                frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&body);
+                Bytes::from(frame)
+            }
+        }
+    }
+
+    fn heartbeat(self) -> Bytes {
+        match self {
+            CborFraming::Sequence => Bytes::from_static(&[SEQUENCE_HEARTBEAT]),
+            CborFraming::LengthPrefixed => Bytes::from_static(&[0; 4]),
+        }
+    }
+}
+
+/// Responder that pushes the items of a stream as CBOR encoded events over a long-lived chunked
+/// response.
+///
+/// Each item is encoded like a [`Cbor`](struct.Cbor.html) body and sent as soon as it is
+/// produced, delimited according to the [`CborFraming`](enum.CborFraming.html), which is a CBOR
+/// sequence by default. The response ends with the stream.
+///
+/// With a [`heartbeat`](#method.heartbeat), an empty event is sent whenever the stream has been
+/// idle for that long, so that proxies and clients don't time out the connection.
+/// [`CborEventStream`](struct.CborEventStream.html) decodes the events on the client and skips
+/// heartbeats.
+///
+/// The [`response_limit`](struct.CborConfig.html#method.response_limit) of the
+/// [`CborConfig`](struct.CborConfig.html) applies to each event. The response is aborted if an
+/// event exceeds it or fails to encode.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use actix_cbor::CborEvents;
+/// use actix_web::get;
+/// use futures_channel::mpsc;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Reading {
+///     sensor: u8,
+///     value: f64,
+/// }
+///
+/// #[get("/readings")]
+/// pub async fn readings() -> CborEvents<mpsc::Receiver<Reading>> {
+///     let (mut tx, events) = CborEvents::channel(16);
+///     actix_rt::spawn(async move {
+///         let _ = tx.try_send(Reading { sensor: 1, value: 21.5 });
+///     });
+///
+///     events.heartbeat(Duration::from_secs(15))
+/// }
+/// ```
+pub struct CborEvents<S> {
+    stream: S,
+    framing: CborFraming,
+    heartbeat: Option<Duration>,
+}
+
+impl<S> CborEvents<S> {
+    /// Create a responder pushing the items of `stream`.
+    pub fn new(stream: S) -> Self {
+        CborEvents {
+            stream,
+            framing: CborFraming::Sequence,
+            heartbeat: None,
+        }
+    }
+
+    /// Change how events are delimited. By default they are sent as a CBOR sequence.
+    pub fn framing(mut self, framing: CborFraming) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Send a heartbeat whenever no event was sent for `interval`. By default there are no
+    /// heartbeats.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+}
+
+impl<T> CborEvents<mpsc::Receiver<T>> {
+    /// Create a responder along with the sender to push events through. At most `buffer` events
+    /// are queued per sender before sending waits for the client. The response ends once all
+    /// senders are dropped.
+    pub fn channel(buffer: usize) -> (mpsc::Sender<T>, Self) {
+        let (tx, rx) = mpsc::channel(buffer);
+        (tx, Self::new(rx))
+    }
+}
+
+impl<S> fmt::Debug for CborEvents<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CborEvents")
+            .field("framing", &self.framing)
+            .field("heartbeat", &self.heartbeat)
+            .finish()
+    }
+}
+
+impl<S> Responder for CborEvents<S>
+    where
+        S: Stream + 'static,
+        S::Item: Serialize,
+{
+    type Error = CborResponseError;
+    type Future = Ready<Result<Response, Self::Error>>;
+
+    fn respond_to(self, req: &HttpRequest) -> Self::Future {
+        let config = CborConfig::from_req(req);
+
+        let events = EventStream {
+            stream: Box::pin(self.stream),
+            framing: self.framing,
+            heartbeat: self.heartbeat.map(|interval| (interval, delay_for(interval))),
+            limit: config.response_limit_for(req),
+            status: config.response_limit_status,
+            done: false,
+        };
+
+        ok(Response::build(StatusCode::OK)
+            .content_type(self.framing.content_type())
+            .header(CACHE_CONTROL, "no-cache")
+            .streaming(events))
+    }
+}
+
+/// Body of a [`CborEvents`](struct.CborEvents.html) response.
+struct EventStream<S> {
+    stream: Pin<Box<S>>,
+    framing: CborFraming,
+    heartbeat: Option<(Duration, Delay)>,
+    limit: Option<usize>,
+    status: StatusCode,
+    done: bool,
+}
+
+impl<S> EventStream<S> where S: Stream, S::Item: Serialize {
+    fn encode(&self, item: &S::Item) -> Result<Bytes, CborResponseError> {
+        let body = serde_cbor::to_vec(item)?;
+
+        if let Some(limit) = self.limit {
+            if body.len() > limit {
+                return Err(CborResponseError::Overflow {
+                    size: body.len(),
+                    limit,
+                    status: self.status,
+                });
+            }
+        }
+
+        Ok(self.framing.frame(body))
+    }
+}
+
+impl<S> Stream for EventStream<S> where S: Stream, S::Item: Serialize {
+    type Item = Result<Bytes, CborResponseError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        match this.stream.as_mut().poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                if let Some((interval, ref mut delay)) = this.heartbeat {
+                    delay.reset(Instant::now() + interval);
+                }
+
+                let event = this.encode(&item);
+                if let Err(ref e) = event {
+                    log::debug!("Failed to push CBOR event, aborting the response: {}", e);
+                    this.done = true;
+                }
+                return Poll::Ready(Some(event));
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => {}
+        }
+
+        if let Some((interval, ref mut delay)) = this.heartbeat {
+            if Pin::new(&mut *delay).poll(cx).is_ready() {
+                delay.reset(Instant::now() + interval);
+                return Poll::Ready(Some(Ok(this.framing.heartbeat())));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Stream decoding the events of a [`CborEvents`](struct.CborEvents.html) response body into `T`.
+///
+/// Heartbeats are skipped, including top-level `undefined` items of a
+/// [`CborFraming::Sequence`](enum.CborFraming.html#variant.Sequence). Events are limited to 32kB by
+/// default, see [`limit`](#method.limit).
+/// The items of a sequence are validated as they arrive, so a malformed item fails with
+/// [`CborPayloadError::Malformed`](enum.CborPayloadError.html#variant.Malformed) without waiting
+/// for the rest of it, and each item is only decoded once it is complete.
+/// The stream ends after the first error.
+///
+/// With the `client` feature, `awc` responses can be decoded with
+/// [`CborClientResponseExt::cbor_events`](trait.CborClientResponseExt.html#tymethod.cbor_events).
+pub struct CborEventStream<S, T> {
+    stream: S,
+    framing: CborFraming,
+    limit: usize,
+    buf: BytesMut,
+    /// Finds the end of each item of a sequence as it arrives, so that it is only decoded once
+    validator: Validator,
+    /// Length of the start of `buf` that was already fed to the validator
+    scanned: usize,
+    done: bool,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<S, T> CborEventStream<S, T> {
+    /// Decode the events of a body `stream` delimited with `framing`.
+    pub fn new(stream: S, framing: CborFraming) -> Self {
+        CborEventStream {
+            stream,
+            framing,
+            limit: DEFAULT_LIMIT,
+            buf: BytesMut::new(),
+            validator: Validator::new(DEFAULT_LIMIT, DEFAULT_MAX_DEPTH).sequence(),
+            scanned: 0,
+            done: false,
+            _item: PhantomData,
+        }
+    }
+
+    /// Change the maximum size of a single event.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self.validator = Validator::new(limit, DEFAULT_MAX_DEPTH).sequence();
+        self
+    }
+}
+
+impl<S, T> CborEventStream<S, T> where T: DeserializeOwned {
+    /// Decode the next buffered event, `Ok(None)` if it is not complete yet.
+    fn decode(&mut self) -> Result<Option<T>, CborPayloadError> {
+        match self.framing {
+            CborFraming::Sequence => {
+                if self.scanned == 0 {
+                    let start = self
+                        .buf
+                        .iter()
+                        .position(|&b| b != SEQUENCE_HEARTBEAT)
+                        .unwrap_or_else(|| self.buf.len());
+                    let _ = self.buf.split_to(start);
+                }
+
+                let len = match self.validator.feed_item(&self.buf[self.scanned..]) {
+                    Ok(Some(len)) => self.scanned + len,
+                    Ok(None) => {
+                        self.scanned = self.buf.len();
+                        return if self.scanned > self.limit {
+                            Err(CborPayloadError::Overflow)
+                        } else {
+                            Ok(None)
+                        };
+                    }
+                    Err(MalformedError::TooLong(_)) => return Err(CborPayloadError::Overflow),
+                    Err(e) => return Err(CborPayloadError::Malformed(e)),
+                };
+                if len > self.limit {
+                    return Err(CborPayloadError::Overflow);
+                }
+
+                self.scanned = 0;
+                let item = self.buf.split_to(len);
+                Ok(Some(serde_cbor::from_slice(&item)?))
+            }
+            CborFraming::LengthPrefixed => loop {
+                if self.buf.len() < 4 {
+                    return Ok(None);
+                }
+
+                let mut prefix = [0; 4];
+                prefix.copy_from_slice(&self.buf[..4]);
+                let len = u32::from_be_bytes(prefix) as usize;
+                if len == 0 {
+                    let _ = self.buf.split_to(4);
+                    continue;
+                }
+                if len > self.limit {
+                    return Err(CborPayloadError::Overflow);
+                }
+                if self.buf.len() < 4 + len {
+                    return Ok(None);
+                }
+
+                let frame = self.buf.split_to(4 + len);
+                return Ok(Some(serde_cbor::from_slice(&frame[4..])?));
+            },
+        }
+    }
+}
+
+impl<S, T> Stream for CborEventStream<S, T>
+    where
+        S: Stream<Item=Result<Bytes, PayloadError>> + Unpin,
+        T: DeserializeOwned,
+{
+    type Item = Result<T, CborPayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            match this.decode() {
+                Ok(Some(item)) => return Poll::Ready(Some(Ok(item))),
+                Ok(None) => {}
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(chunk)) => this.buf.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                None => {
+                    this.done = true;
+                    if !this.buf.is_empty() {
+                        return Poll::Ready(Some(Err(PayloadError::Incomplete(None).into())));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S, T> fmt::Debug for CborEventStream<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CborEventStream")
+            .field("framing", &self.framing)
+            .field("limit", &self.limit)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
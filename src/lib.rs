@@ -1,10 +1,15 @@
 //! # Example
 //! ```
 //! use actix_cbor::Cbor;
+//! use actix_web::get;
+//! use serde::{Deserialize, Serialize};
 //!
+//! #[derive(Deserialize)]
 //! struct User {
 //!     name: String,
 //! }
+//!
+//! #[derive(Serialize)]
 //! struct Greeting {
 //!     inner: String,
 //! }
@@ -21,27 +26,84 @@
 #[macro_use]
 extern crate serde;
 
+// lets `#[derive(CddlSchema)]` refer to this crate in its own tests
+#[cfg(all(test, feature = "cddl"))]
+extern crate self as actix_cbor;
+
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 
 use actix_http::{Payload, PayloadStream, Response};
 use actix_http::http::StatusCode;
+use actix_http::http::header::{ACCEPT_RANGES, CONTENT_RANGE, ETAG};
 use actix_web::{FromRequest, HttpRequest, Responder};
-#[cfg(feature = "compress")]
-use actix_web::dev::Decompress;
-use futures_util::core_reexport::fmt::Formatter;
+use actix_web::web::Bytes;
 use futures_util::future::{err, LocalBoxFuture, ok, Ready};
 use futures_util::FutureExt;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 pub use body::*;
+#[cfg(feature = "cddl")]
+pub use actix_cbor_derive::CddlSchema;
+#[cfg(feature = "cddl")]
+pub use cddl::*;
+pub use coap::*;
+#[cfg(feature = "client")]
+pub use client::*;
 pub use config::*;
+#[cfg(feature = "cose")]
+pub use cose::*;
+#[cfg(feature = "compress")]
+pub use dictionary::*;
 pub use error::*;
+pub use error_response::*;
+pub use events::*;
+pub use params::*;
+pub use pool::*;
+#[cfg(feature = "cose")]
+pub use replay::*;
+pub use streamed::*;
+pub use validate::MalformedError;
+#[cfg(feature = "multipart")]
+pub use multipart::*;
+#[cfg(feature = "openapi")]
+pub use openapi::cbor_request_body;
+#[cfg(feature = "ws")]
+pub use ws::*;
 
 mod error;
+mod error_response;
 mod config;
 mod body;
+#[cfg(feature = "cddl")]
+mod cddl;
+mod coap;
+#[cfg(feature = "cose")]
+mod cose;
+mod events;
+mod params;
+#[cfg(feature = "compress")]
+mod dictionary;
+#[cfg(feature = "compress")]
+mod encoding;
+mod etag;
+mod range;
+mod pool;
+#[cfg(feature = "cose")]
+mod replay;
+mod streamed;
+mod validate;
+#[cfg(feature = "client")]
+mod client;
+#[cfg(feature = "multipart")]
+mod multipart;
+#[cfg(feature = "openapi")]
+mod openapi;
+#[cfg(feature = "ws")]
+mod ws;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test;
 
 #[cfg(test)]
 mod tests;
@@ -55,10 +117,15 @@ mod tests;
 /// # Example
 /// ```
 /// use actix_cbor::Cbor;
+/// use actix_web::get;
+/// use serde::{Deserialize, Serialize};
 ///
+/// #[derive(Deserialize)]
 /// struct User {
 ///     name: String,
 /// }
+///
+/// #[derive(Serialize)]
 /// struct Greeting {
 ///     inner: String,
 /// }
@@ -94,25 +161,110 @@ impl<T> DerefMut for Cbor<T> {
 }
 
 impl<T> fmt::Debug for Cbor<T> where T: fmt::Debug {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Cbor: {:?}", self.0)
     }
 }
 
 impl<T> Responder for Cbor<T> where T: Serialize {
-    type Error = CborError;
+    type Error = CborResponseError;
     type Future = Ready<Result<Response, Self::Error>>;
 
-    fn respond_to(self, _: &HttpRequest) -> Self::Future {
-        let body = match serde_cbor::to_vec(&self.0) {
-            Ok(body) => body,
-            Err(e) => return err(e.into())
+    fn respond_to(self, req: &HttpRequest) -> Self::Future {
+        let config = CborConfig::from_req(req);
+
+        let body = if config.etag {
+            etag::to_vec_deterministic(&self.0)
+        } else {
+            serde_cbor::to_vec(&self.0)
         };
+        match body {
+            Ok(body) => respond_encoded(body, "application/cbor", req, &config),
+            Err(e) => err(e.into()),
+        }
+    }
+}
+
+/// Respond with an encoded CBOR `body`, applying the response limit, compression, ETag and
+/// ranges of `config`.
+pub(crate) fn respond_encoded(
+    body: Vec<u8>,
+    content_type: &str,
+    req: &HttpRequest,
+    config: &CborConfig,
+) -> Ready<Result<Response, CborResponseError>> {
+    if let Some(limit) = config.response_limit_for(req) {
+        if body.len() > limit {
+            log::debug!(
+                "CBOR response of {} bytes is over the limit of {} bytes. \
+                 Request path: {}",
+                body.len(),
+                limit,
+                req.path()
+            );
 
-        ok(Response::build(StatusCode::OK)
-            .content_type("application/cbor")
-            .body(body))
+            return err(CborResponseError::Overflow {
+                size: body.len(),
+                limit,
+                status: config.response_limit_status,
+            });
+        }
     }
+
+    let mut res = Response::build(StatusCode::OK);
+    res.content_type(content_type);
+
+    #[cfg(feature = "compress")]
+        let encoding = config.compress_min_size.and_then(|min_size| {
+        encoding::ResponseEncoding::negotiate(req, min_size, body.len(), &mut res)
+    });
+
+    let tag = if config.etag {
+        #[cfg(feature = "compress")]
+            let variant = encoding.as_ref().map(|encoding| encoding.variant());
+        #[cfg(not(feature = "compress"))]
+            let variant: Option<String> = None;
+
+        let tag = etag::entity_tag(&body, variant.as_deref());
+        res.header(ETAG, tag.as_str());
+
+        if etag::not_modified(req, &tag) {
+            return ok(res.status(StatusCode::NOT_MODIFIED).finish());
+        }
+        Some(tag)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "compress")]
+        let body = match encoding {
+        Some(encoding) => encoding.encode(&mut res, body),
+        None => body,
+    };
+
+    if config.ranges {
+        res.header(ACCEPT_RANGES, "bytes");
+
+        let len = body.len();
+        match range::requested(req, len, tag.as_deref()) {
+            Some(Ok(range)) => {
+                let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+                return ok(res
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, content_range)
+                    .body(Bytes::from(body).slice(range)));
+            }
+            Some(Err(())) => {
+                return ok(res
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .finish());
+            }
+            None => {}
+        }
+    }
+
+    ok(res.body(body))
 }
 
 impl<T> FromRequest for Cbor<T> where T: DeserializeOwned + 'static {
@@ -122,30 +274,33 @@ impl<T> FromRequest for Cbor<T> where T: DeserializeOwned + 'static {
 
     fn from_request(req: &HttpRequest, payload: &mut Payload<PayloadStream>) -> Self::Future {
         let req2 = req.clone();
-        let config = CborConfig::from_req(req);
+        let config = CborConfig::from_req_for::<T>(req);
 
-        let limit = config.limit;
-        let ctype = config.content_type.clone();
-        let err_handler = config.err_handler.clone();
+        let mut body = CborBody::new(req, payload, config.content_type.clone())
+            .limit(config.limit)
+            .incremental(config.incremental)
+            .max_depth(config.max_depth);
+        #[cfg(feature = "compress")]
+            {
+                body.compressed_limit = config.compressed_limit;
+            }
+        let body = body.raw_future();
+        let handlers = config.error_handlers();
 
-        CborBody::new(req, payload, ctype)
-            .limit(limit)
-            .map(move |res| match res {
-                Err(e) => {
+        async move {
+            match body.await {
+                Err((e, raw)) => {
                     log::debug!(
                         "Failed to deserialize CBOR from payload. \
                          Request path: {}",
                         req2.path()
                     );
 
-                    if let Some(err) = err_handler {
-                        Err((*err)(e, &req2))
-                    } else {
-                        Err(e.into())
-                    }
+                    Err(handlers.handle(e, &req2, raw).await)
                 }
                 Ok(data) => Ok(Cbor(data)),
-            })
+            }
+        }
             .boxed_local()
     }
 }
\ No newline at end of file
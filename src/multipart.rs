@@ -0,0 +1,595 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "compress")]
+use actix_http::error::PayloadError;
+use actix_http::{HttpMessage, Payload, PayloadStream};
+use actix_http::http::{HeaderMap, HeaderName, HeaderValue};
+use actix_http::http::header::{CONTENT_TYPE, ContentDisposition, CONTENT_DISPOSITION};
+#[cfg(feature = "compress")]
+use actix_http::http::header::{CONTENT_ENCODING, ContentEncoding};
+#[cfg(feature = "compress")]
+use actix_web::dev::Decompress;
+use actix_web::{FromRequest, HttpRequest};
+use actix_web::web::{Bytes, BytesMut};
+use futures_util::future::{FutureExt, LocalBoxFuture, ok};
+use futures_util::ready;
+use futures_util::stream::Stream;
+use serde::de::DeserializeOwned;
+
+use crate::{CborConfig, CborPayloadError};
+#[cfg(feature = "compress")]
+use crate::body::WireLimit;
+use crate::body::{initial_capacity, is_cbor_content_type, read_body};
+use crate::config::ContentTypePredicate;
+
+/// Maximum size of the headers of a single part.
+const MAX_HEADERS_SIZE: usize = 8192;
+
+/// Maximum number of headers in a single part.
+const MAX_HEADERS: usize = 32;
+
+/// Errors that can occur while reading a multipart body.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// Request is not `multipart/form-data` or `multipart/mixed`, or has no boundary
+    ContentType,
+    /// A part's headers could not be parsed
+    Headers,
+    /// A boundary is not followed by a line break or `--`
+    Boundary,
+    /// The payload ended before the closing boundary
+    Incomplete,
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::ContentType => write!(f, "Multipart content type error"),
+            MultipartError::Headers => write!(f, "Multipart part headers are invalid"),
+            MultipartError::Boundary => write!(f, "Multipart boundary is malformed"),
+            MultipartError::Incomplete => write!(f, "Multipart stream is incomplete"),
+        }
+    }
+}
+
+/// Extractor for `multipart/form-data` and `multipart/mixed` requests carrying CBOR parts.
+///
+/// Parts are yielded in order as [`CborField`](struct.CborField.html)s. CBOR parts can be decoded
+/// with [`CborField::cbor`](struct.CborField.html#method.cbor), which applies the limit and
+/// content type predicate from [`CborConfig`](struct.CborConfig.html) to each part, while any
+/// other part, such as a file upload, can be streamed chunk by chunk. A config registered with
+/// `for_type::<CborMultipart>(..)` only applies to this extractor.
+///
+/// With the `compress` feature, a body with a `Content-Encoding` is checked as a whole against the
+/// limit after decompression and against the compressed limit on the wire, like any other
+/// compressed payload.
+///
+/// # Example
+/// ```
+/// use actix_cbor::CborMultipart;
+/// use actix_web::{post, Error, HttpResponse};
+/// use futures_util::{StreamExt, TryStreamExt};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Metadata {
+///     version: String,
+/// }
+///
+/// #[post("/firmware")]
+/// pub async fn upload(mut form: CborMultipart) -> Result<HttpResponse, Error> {
+///     while let Some(mut field) = form.try_next().await? {
+///         match field.name() {
+///             Some("metadata") => {
+///                 let meta: Metadata = field.cbor().await?;
+///             }
+///             _ => {
+///                 while let Some(chunk) = field.next().await {
+///                     let chunk = chunk?;
+///                 }
+///             }
+///         }
+///     }
+///
+///     Ok(HttpResponse::Ok().finish())
+/// }
+/// ```
+pub struct CborMultipart {
+    inner: Option<Rc<RefCell<Inner>>>,
+    /// Payload and boundary, until the first part is read
+    pending: Option<(Payload, Vec<u8>)>,
+    err: Option<CborPayloadError>,
+    limit: usize,
+    ctype: Option<ContentTypePredicate>,
+    #[cfg(feature = "compress")]
+    encoding: ContentEncoding,
+    #[cfg(feature = "compress")]
+    pub(crate) compressed_limit: Option<usize>,
+}
+
+impl CborMultipart {
+    /// Create `CborMultipart` for request.
+    pub fn new(
+        req: &HttpRequest,
+        payload: &mut Payload,
+        limit: usize,
+        ctype: Option<ContentTypePredicate>,
+    ) -> Self {
+        let (pending, err) = match boundary(req) {
+            Ok(boundary) => (Some((payload.take(), boundary)), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        CborMultipart {
+            inner: None,
+            pending,
+            err,
+            limit,
+            ctype,
+            #[cfg(feature = "compress")]
+            encoding: req
+                .headers()
+                .get(&CONTENT_ENCODING)
+                .and_then(|enc| enc.to_str().ok())
+                .map(ContentEncoding::from)
+                .unwrap_or(ContentEncoding::Identity),
+            #[cfg(feature = "compress")]
+            compressed_limit: None,
+        }
+    }
+
+    /// Change max size of a compressed body as received on the wire. By default this is the same
+    /// as the limit.
+    #[cfg(feature = "compress")]
+    pub fn compressed_limit(mut self, limit: usize) -> Self {
+        self.compressed_limit = Some(limit);
+        self
+    }
+
+    /// Start reading the payload, decompressing it if needed.
+    fn start(&mut self, payload: Payload, boundary: Vec<u8>) -> Inner {
+        #[cfg(feature = "compress")]
+            let (payload, limit): (PayloadStream, _) = match self.encoding {
+            ContentEncoding::Identity => (Box::pin(payload), None),
+            encoding => {
+                let wire_limit = self.compressed_limit.unwrap_or(self.limit);
+                let stream = WireLimit::new(payload, Some(wire_limit));
+                (Box::pin(Decompress::new(stream, encoding)), Some(self.limit))
+            }
+        };
+        #[cfg(not(feature = "compress"))]
+            let (payload, limit): (PayloadStream, _) = (Box::pin(payload), None);
+
+        Inner {
+            payload,
+            // the first boundary has to start a line, which the start of the body counts as
+            buf: BytesMut::from(&b"\r\n"[..]),
+            boundary,
+            state: State::Preamble,
+            field: 0,
+            eof: false,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+fn boundary(req: &HttpRequest) -> Result<Vec<u8>, CborPayloadError> {
+    let mime = req
+        .mime_type()
+        .ok()
+        .flatten()
+        .ok_or(CborPayloadError::Multipart(MultipartError::ContentType))?;
+
+    if mime.type_() != "multipart" || (mime.subtype() != "form-data" && mime.subtype() != "mixed") {
+        return Err(CborPayloadError::Multipart(MultipartError::ContentType));
+    }
+
+    let boundary = mime
+        .get_param("boundary")
+        .ok_or(CborPayloadError::Multipart(MultipartError::ContentType))?;
+
+    Ok(format!("--{}", boundary.as_str()).into_bytes())
+}
+
+impl Stream for CborMultipart {
+    type Item = Result<CborField, CborPayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(err) = self.err.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        if let Some((payload, boundary)) = self.pending.take() {
+            let inner = self.start(payload, boundary);
+            self.inner = Some(Rc::new(RefCell::new(inner)));
+        }
+
+        let inner = match self.inner {
+            Some(ref inner) => inner.clone(),
+            None => return Poll::Ready(None),
+        };
+
+        let headers = match inner.borrow_mut().poll_headers(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Ok(Some(headers))) => headers,
+            Poll::Ready(Ok(None)) => {
+                self.inner = None;
+                return Poll::Ready(None);
+            }
+            Poll::Ready(Err(e)) => {
+                self.inner = None;
+                return Poll::Ready(Some(Err(e)));
+            }
+        };
+
+        let id = inner.borrow().field;
+        let disposition = headers
+            .get(&CONTENT_DISPOSITION)
+            .and_then(|hv| ContentDisposition::from_raw(hv).ok());
+
+        Poll::Ready(Some(Ok(CborField {
+            headers,
+            disposition,
+            inner,
+            id,
+            limit: self.limit,
+            ctype: self.ctype.clone(),
+        })))
+    }
+}
+
+impl FromRequest for CborMultipart {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = CborConfig;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload<PayloadStream>) -> Self::Future {
+        let config = CborConfig::from_req_for::<Self>(req);
+
+        let mut multipart = CborMultipart::new(
+            req,
+            payload,
+            config.limit,
+            config.content_type.clone(),
+        );
+        #[cfg(feature = "compress")]
+            {
+                multipart.compressed_limit = config.compressed_limit;
+            }
+
+        match multipart.err.take() {
+            Some(e) => {
+                log::debug!(
+                    "Failed to read multipart CBOR payload. \
+                     Request path: {}",
+                    req.path()
+                );
+
+                config.error_handlers().handle(e, req, None).map(Err).boxed_local()
+            }
+            None => ok(multipart).boxed_local(),
+        }
+    }
+}
+
+/// A single part of a [`CborMultipart`](struct.CborMultipart.html) body.
+///
+/// The raw part body is available as a stream of chunks; CBOR parts can be decoded directly with
+/// [`cbor`](#method.cbor).
+pub struct CborField {
+    headers: HeaderMap,
+    disposition: Option<ContentDisposition>,
+    inner: Rc<RefCell<Inner>>,
+    id: usize,
+    limit: usize,
+    ctype: Option<ContentTypePredicate>,
+}
+
+impl CborField {
+    /// Headers of this part.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Content type of this part, or an empty string if there is none.
+    pub fn content_type(&self) -> &str {
+        self.headers
+            .get(&CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .and_then(|ct| ct.split(';').next())
+            .map(str::trim)
+            .unwrap_or("")
+    }
+
+    /// Parsed `Content-Disposition` of this part, if present.
+    pub fn content_disposition(&self) -> Option<&ContentDisposition> {
+        self.disposition.as_ref()
+    }
+
+    /// Form field name of this part, if present.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition.as_ref().and_then(|cd| cd.get_name())
+    }
+
+    /// File name of this part, if present.
+    pub fn filename(&self) -> Option<&str> {
+        self.disposition.as_ref().and_then(|cd| cd.get_filename())
+    }
+
+    /// Whether this part has a CBOR content type.
+    pub fn is_cbor(&self) -> bool {
+        is_cbor_content_type(self.content_type(), self.ctype.as_ref())
+    }
+
+    /// Read the rest of this part and decode it as CBOR.
+    ///
+    /// Fails if the part does not have a CBOR content type or is larger than the configured limit.
+    pub async fn cbor<T: DeserializeOwned>(&mut self) -> Result<T, CborPayloadError> {
+        if !self.is_cbor() {
+            return Err(CborPayloadError::ContentType);
+        }
+
+        let limit = self.limit;
+        let buf = BytesMut::with_capacity(initial_capacity(None, limit));
+        let body = read_body(self, buf, limit, None).await?;
+        Ok(serde_cbor::from_slice::<T>(&body)?)
+    }
+}
+
+impl Stream for CborField {
+    type Item = Result<Bytes, CborPayloadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut inner = self.inner.borrow_mut();
+
+        if inner.field != self.id {
+            return Poll::Ready(None);
+        }
+
+        inner.poll_chunk(cx)
+    }
+}
+
+impl fmt::Debug for CborField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CborField")
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+#[derive(PartialEq)]
+enum State {
+    Preamble,
+    /// After a boundary, before the line break or `--` that follows it
+    Boundary,
+    Headers,
+    Field,
+    Done,
+}
+
+struct Inner {
+    payload: PayloadStream,
+    buf: BytesMut,
+    /// Boundary including the leading `--`
+    boundary: Vec<u8>,
+    state: State,
+    /// Incremented for every part, so stale `CborField`s stop yielding data
+    field: usize,
+    eof: bool,
+    /// Limit on the decompressed size of a compressed body
+    limit: Option<usize>,
+    read: usize,
+}
+
+impl Inner {
+    /// Read another chunk of the payload into the buffer.
+    ///
+    /// Fails with `Incomplete` if the payload has already ended.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), CborPayloadError>> {
+        if self.eof {
+            return Poll::Ready(Err(CborPayloadError::Multipart(MultipartError::Incomplete)));
+        }
+
+        match Pin::new(&mut self.payload).poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(Ok(chunk))) => {
+                self.read += chunk.len();
+                if self.limit.is_some_and(|limit| self.read > limit) {
+                    return Poll::Ready(Err(CborPayloadError::Overflow));
+                }
+
+                self.buf.extend_from_slice(&chunk);
+                Poll::Ready(Ok(()))
+            }
+            #[cfg(feature = "compress")]
+            Poll::Ready(Some(Err(PayloadError::Overflow))) if self.limit.is_some() => {
+                Poll::Ready(Err(CborPayloadError::CompressedOverflow))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Err(e.into())),
+            Poll::Ready(None) => {
+                self.eof = true;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    /// Skip to the next part and parse its headers, or resolve to `None` after the last part.
+    fn poll_headers(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, CborPayloadError>> {
+        loop {
+            match self.state {
+                State::Done => return Poll::Ready(Ok(None)),
+                State::Field => {
+                    // drain whatever is left of the previous part
+                    match self.poll_chunk(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                        Poll::Ready(_) => continue,
+                    }
+                }
+                State::Preamble => {
+                    // the delimiter is CRLF followed by the boundary
+                    let delimiter_len = self.boundary.len() + 2;
+
+                    match find_delimiter(&self.buf, &self.boundary) {
+                        Some(idx) => self.skip_boundary(idx + delimiter_len),
+                        None => {
+                            // the preamble is ignored, so only hold on to what could be the
+                            // start of a delimiter
+                            if self.buf.len() >= delimiter_len {
+                                let len = self.buf.len() - (delimiter_len - 1);
+                                let _ = self.buf.split_to(len);
+                            }
+
+                            if let Err(e) = ready!(self.poll_fill(cx)) {
+                                return Poll::Ready(Err(e));
+                            }
+                        }
+                    }
+                }
+                State::Boundary => match self.after_boundary() {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => return Poll::Ready(Err(e)),
+                    None => {
+                        if let Err(e) = ready!(self.poll_fill(cx)) {
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                },
+                State::Headers => {
+                    // a part without headers starts with the empty line right away
+                    let end = if self.buf.starts_with(b"\r\n") {
+                        Some(2)
+                    } else {
+                        find(&self.buf, b"\r\n\r\n").map(|idx| idx + 4)
+                    };
+
+                    match end {
+                        Some(end) => {
+                            let raw = self.buf.split_to(end);
+                            self.state = State::Field;
+                            return Poll::Ready(parse_headers(&raw).map(Some));
+                        }
+                        None if self.buf.len() > MAX_HEADERS_SIZE => {
+                            return Poll::Ready(Err(CborPayloadError::Multipart(MultipartError::Headers)));
+                        }
+                        None => {
+                            if let Err(e) = ready!(self.poll_fill(cx)) {
+                                return Poll::Ready(Err(e));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the next chunk of the current part's body.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, CborPayloadError>>> {
+        // the delimiter is CRLF followed by the boundary
+        let delimiter_len = self.boundary.len() + 2;
+
+        loop {
+            if self.state != State::Field {
+                return Poll::Ready(None);
+            }
+
+            let delimiter = find_delimiter(&self.buf, &self.boundary);
+
+            match delimiter {
+                Some(0) => {
+                    self.skip_boundary(delimiter_len);
+                    return Poll::Ready(None);
+                }
+                Some(idx) => return Poll::Ready(Some(Ok(self.buf.split_to(idx).freeze()))),
+                None => {
+                    // keep enough bytes around to match a delimiter split across chunks
+                    let keep = delimiter_len - 1;
+                    if self.buf.len() > keep {
+                        let len = self.buf.len() - keep;
+                        return Poll::Ready(Some(Ok(self.buf.split_to(len).freeze())));
+                    }
+                }
+            }
+
+            if let Err(e) = ready!(self.poll_fill(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+
+    /// Consume the first `len` bytes of the buffer, which end with a boundary, and end the
+    /// current part.
+    fn skip_boundary(&mut self, len: usize) {
+        let _ = self.buf.split_to(len);
+        self.field += 1;
+        self.state = State::Boundary;
+    }
+
+    /// Consume the `--` closing the body, or the optional whitespace and CRLF starting the next
+    /// part, after a boundary. Returns `None` if more of the payload is needed to tell.
+    fn after_boundary(&mut self) -> Option<Result<(), CborPayloadError>> {
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Some(Ok(()));
+        }
+
+        let padding = self.buf.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        match self.buf.get(padding..padding + 2) {
+            Some(b"\r\n") => {
+                let _ = self.buf.split_to(padding + 2);
+                self.state = State::Headers;
+                Some(Ok(()))
+            }
+            None if padding <= MAX_HEADERS_SIZE => None,
+            _ => Some(Err(CborPayloadError::Multipart(MultipartError::Boundary))),
+        }
+    }
+}
+
+fn parse_headers(raw: &[u8]) -> Result<HeaderMap, CborPayloadError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+
+    match httparse::parse_headers(raw, &mut headers) {
+        Ok(httparse::Status::Complete((_, parsed))) => {
+            let mut map = HeaderMap::new();
+            for header in parsed {
+                let name = HeaderName::from_bytes(header.name.as_bytes());
+                let value = HeaderValue::from_bytes(header.value);
+                match (name, value) {
+                    (Ok(name), Ok(value)) => map.append(name, value),
+                    _ => return Err(CborPayloadError::Multipart(MultipartError::Headers)),
+                }
+            }
+            Ok(map)
+        }
+        _ => Err(CborPayloadError::Multipart(MultipartError::Headers)),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Find a CRLF followed by the boundary. A partial match at the end of the buffer is not reported.
+fn find_delimiter(haystack: &[u8], boundary: &[u8]) -> Option<usize> {
+    let mut start = 0;
+
+    while let Some(idx) = find(&haystack[start..], b"\r\n") {
+        let idx = start + idx;
+        let rest = &haystack[idx + 2..];
+
+        if rest.starts_with(boundary) {
+            return Some(idx);
+        }
+
+        start = idx + 1;
+    }
+
+    None
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use utoipa::openapi::{Content, RefOr, Required, Response, ResponseBuilder, ResponsesBuilder};
+use utoipa::openapi::request_body::{RequestBody, RequestBodyBuilder};
+use utoipa::{IntoResponses, ToSchema};
+
+use crate::{Cbor, CborError, CborPayloadError, CborResponseError, CborStreamed};
+
+/// Content type of documented request and response bodies.
+const CONTENT_TYPE: &str = "application/cbor";
+
+/// OpenAPI request body of a `Cbor<T>` extractor, for use in a
+/// [`Modify`](https://docs.rs/utoipa/5/utoipa/trait.Modify.html) implementation or a hand
+/// written path.
+///
+/// In `#[utoipa::path]`, use `request_body(content = T, content_type = "application/cbor")`
+/// instead.
+pub fn cbor_request_body<T: ToSchema>() -> RequestBody {
+    RequestBodyBuilder::new()
+        .content(CONTENT_TYPE, Content::new(Some(T::schema())))
+        .required(Some(Required::True))
+        .build()
+}
+
+/// Successful response with a CBOR encoded `T`.
+fn ok_response<T: ToSchema>() -> BTreeMap<String, RefOr<Response>> {
+    ResponsesBuilder::new()
+        .response(
+            "200",
+            ResponseBuilder::new()
+                .description("CBOR encoded response")
+                .content(CONTENT_TYPE, Content::new(Some(T::schema()))),
+        )
+        .build()
+        .into()
+}
+
+/// `200 OK` with an `application/cbor` body of `T`.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborPayloadError, CborResponseError};
+/// use actix_web::post;
+/// use serde::{Deserialize, Serialize};
+/// use utoipa::ToSchema;
+///
+/// #[derive(Deserialize, ToSchema)]
+/// struct User {
+///     name: String,
+/// }
+///
+/// #[derive(Serialize, ToSchema)]
+/// struct Greeting {
+///     inner: String,
+/// }
+///
+/// #[utoipa::path(
+///     post,
+///     path = "/users/hello",
+///     request_body(content = User, content_type = "application/cbor"),
+///     responses(Cbor<Greeting>, CborPayloadError, CborResponseError)
+/// )]
+/// #[post("/users/hello")]
+/// pub async fn greet_user(user: Cbor<User>) -> Cbor<Greeting> {
+///     Cbor(Greeting { inner: format!("Hello {}!", user.name) })
+/// }
+/// ```
+impl<T: ToSchema> IntoResponses for Cbor<T> {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        ok_response::<T>()
+    }
+}
+
+/// `200 OK` with an `application/cbor` body of `T`.
+impl<T: ToSchema> IntoResponses for CborStreamed<T> {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        ok_response::<T>()
+    }
+}
+
+/// `400 Bad Request` and `413 Payload Too Large`, and with the `cose` feature
+/// `401 Unauthorized`.
+impl IntoResponses for CborPayloadError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        let responses = ResponsesBuilder::new()
+            .response(
+                "400",
+                ResponseBuilder::new()
+                    .description("Payload is not valid CBOR, or has the wrong content type"),
+            )
+            .response(
+                "413",
+                ResponseBuilder::new().description("CBOR payload is bigger than allowed"),
+            );
+
+        #[cfg(feature = "cose")]
+            let responses = responses.response(
+            "401",
+            ResponseBuilder::new()
+                .description("COSE payload is not authenticated by a known key, or is expired or replayed"),
+        );
+
+        responses.build().into()
+    }
+}
+
+/// `500 Internal Server Error`.
+impl IntoResponses for CborError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        ResponsesBuilder::new()
+            .response(
+                "500",
+                ResponseBuilder::new().description("Response could not be encoded as CBOR"),
+            )
+            .build()
+            .into()
+    }
+}
+
+/// `500 Internal Server Error`, which is also the default status of
+/// [`response_limit`](struct.CborConfig.html#method.response_limit) overflows.
+impl IntoResponses for CborResponseError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        ResponsesBuilder::new()
+            .response(
+                "500",
+                ResponseBuilder::new()
+                    .description("Response could not be encoded as CBOR, or is bigger than allowed"),
+            )
+            .build()
+            .into()
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use actix_http::{Payload, PayloadStream};
+use actix_web::{FromRequest, HttpRequest, web};
+use actix_web::web::Bytes;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+
+use crate::{Base64Error, CborConfig, CborPayloadError};
+
+/// Default query parameter read by [`CborQuery`](struct.CborQuery.html).
+pub const DEFAULT_QUERY_PARAM: &str = "cbor";
+
+/// Extractor for a base64url-encoded CBOR document in a query parameter.
+///
+/// The document is read from the `cbor` parameter, unless changed with
+/// [`CborConfig::query_param`](struct.CborConfig.html#method.query_param). Padding is optional.
+/// The limit and error handler of the [`CborConfig`](struct.CborConfig.html) apply, with the
+/// limit checked against the decoded size.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborQuery};
+/// use actix_web::get;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Filter {
+///     tags: Vec<String>,
+///     since: u64,
+/// }
+///
+/// // GET /readings?cbor=omR0YWdzgWR0ZW1wZXNpbmNlGgAB4kA
+/// #[get("/readings")]
+/// pub async fn readings(filter: CborQuery<Filter>) -> Cbor<Vec<u64>> {
+///     Cbor(vec![filter.since])
+/// }
+/// ```
+pub struct CborQuery<T>(pub T);
+
+/// Extractor for a base64url-encoded CBOR document in a path segment.
+///
+/// The document is read from the first dynamic segment of the route, unless a segment is named
+/// with [`CborConfig::path_param`](struct.CborConfig.html#method.path_param). Padding is optional.
+/// The limit and error handler of the [`CborConfig`](struct.CborConfig.html) apply, with the
+/// limit checked against the decoded size.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborPath};
+/// use actix_web::get;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Key {
+///     device: u32,
+///     sensor: u8,
+/// }
+///
+/// // GET /sensors/omZkZXZpY2UYKmZzZW5zb3ID
+/// #[get("/sensors/{key}")]
+/// pub async fn sensor(key: CborPath<Key>) -> Cbor<u8> {
+///     Cbor(key.sensor)
+/// }
+/// ```
+pub struct CborPath<T>(pub T);
+
+/// Name of the header read by [`CborHeader<T, H>`](struct.CborHeader.html).
+pub trait CborHeaderName {
+    /// Header name, in lowercase
+    const NAME: &'static str;
+}
+
+/// Extractor for a base64url-encoded CBOR document in the header named by `H`.
+///
+/// Padding is optional. If the header is repeated, the first value is used. The limit and error
+/// handler of the [`CborConfig`](struct.CborConfig.html) apply, with the limit checked against
+/// the decoded size. A missing header fails with
+/// [`CborPayloadError::MissingParameter`](enum.CborPayloadError.html#variant.MissingParameter).
+///
+/// # Example
+/// ```
+/// use actix_cbor::{Cbor, CborHeader, CborHeaderName};
+/// use actix_web::get;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct TraceContext {
+///     trace_id: u64,
+///     sampled: bool,
+/// }
+///
+/// struct TraceHeader;
+///
+/// impl CborHeaderName for TraceHeader {
+///     const NAME: &'static str = "x-trace-context";
+/// }
+///
+/// #[get("/status")]
+/// pub async fn status(trace: CborHeader<TraceContext, TraceHeader>) -> Cbor<u64> {
+///     Cbor(trace.trace_id)
+/// }
+/// ```
+pub struct CborHeader<T, H>(pub T, PhantomData<fn() -> H>);
+
+macro_rules! impl_wrapper {
+    ($name:ident) => {
+        impl<T> $name<T> {
+            /// Deconstruct to an inner value
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+
+        impl<T> fmt::Debug for $name<T> where T: fmt::Debug {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!(stringify!($name), ": {:?}"), self.0)
+            }
+        }
+    };
+}
+
+impl_wrapper!(CborQuery);
+impl_wrapper!(CborPath);
+
+impl<T, H> CborHeader<T, H> {
+    /// Wrap a value
+    pub fn new(inner: T) -> Self {
+        CborHeader(inner, PhantomData)
+    }
+
+    /// Deconstruct to an inner value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, H> Deref for CborHeader<T, H> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, H> DerefMut for CborHeader<T, H> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T, H> fmt::Debug for CborHeader<T, H> where T: fmt::Debug, H: CborHeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CborHeader({}): {:?}", H::NAME, self.0)
+    }
+}
+
+impl<T> FromRequest for CborQuery<T> where T: DeserializeOwned + 'static {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = CborConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload<PayloadStream>) -> Self::Future {
+        let config = CborConfig::from_req_for::<T>(req);
+        let name = config.query_param.as_deref().unwrap_or(DEFAULT_QUERY_PARAM);
+
+        let param = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .ok()
+            .and_then(|mut query| query.remove(name));
+        let res = decode_param(param.as_ref().map(|param| param.as_bytes()), config.limit);
+
+        extract(req, &config, res, CborQuery)
+    }
+}
+
+impl<T> FromRequest for CborPath<T> where T: DeserializeOwned + 'static {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = CborConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload<PayloadStream>) -> Self::Future {
+        let config = CborConfig::from_req_for::<T>(req);
+
+        let param = match config.path_param {
+            Some(ref name) => req.match_info().get(name),
+            None => req.match_info().iter().next().map(|(_, value)| value),
+        };
+        let res = decode_param(param.map(str::as_bytes), config.limit);
+
+        extract(req, &config, res, CborPath)
+    }
+}
+
+impl<T, H> FromRequest for CborHeader<T, H>
+    where
+        T: DeserializeOwned + 'static,
+        H: CborHeaderName + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = CborConfig;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload<PayloadStream>) -> Self::Future {
+        let config = CborConfig::from_req_for::<T>(req);
+
+        // non-ASCII values are rejected by the base64 decoder
+        let param = req.headers().get(H::NAME).map(|value| value.as_bytes());
+        let res = decode_param(param, config.limit);
+
+        extract(req, &config, res, CborHeader::new)
+    }
+}
+
+/// Finish extraction of a decoded parameter, running the error handler on failure.
+fn extract<T, E>(
+    req: &HttpRequest,
+    config: &CborConfig,
+    res: Result<T, (CborPayloadError, Option<Bytes>)>,
+    wrap: fn(T) -> E,
+) -> LocalBoxFuture<'static, Result<E, actix_web::Error>>
+    where
+        T: 'static,
+        E: 'static,
+{
+    let req = req.clone();
+    let handlers = config.error_handlers();
+
+    async move {
+        match res {
+            Err((e, raw)) => {
+                log::debug!(
+                    "Failed to deserialize CBOR from parameter or header. \
+                     Request path: {}",
+                    req.path()
+                );
+
+                Err(handlers.handle(e, &req, raw).await)
+            }
+            Ok(data) => Ok(wrap(data)),
+        }
+    }
+        .boxed_local()
+}
+
+/// Decode a base64url-encoded CBOR parameter, along with the decoded bytes if deserializing fails.
+fn decode_param<T>(
+    param: Option<&[u8]>,
+    limit: usize,
+) -> Result<T, (CborPayloadError, Option<Bytes>)>
+    where
+        T: DeserializeOwned,
+{
+    let param = param.ok_or((CborPayloadError::MissingParameter, None))?;
+    let param = &param[..param.iter().rposition(|&b| b != b'=').map_or(0, |i| i + 1)];
+
+    // check the decoded size before decoding
+    if param.len() / 4 * 3 + (param.len() % 4).saturating_sub(1) > limit {
+        return Err((CborPayloadError::Overflow, None));
+    }
+
+    let bytes = base64::decode_config(param, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| (CborPayloadError::Base64(Base64Error::new(e)), None))?;
+
+    serde_cbor::from_slice(&bytes).map_err(|e| (e.into(), Some(Bytes::from(bytes))))
+}
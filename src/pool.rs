@@ -0,0 +1,97 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use actix_web::{HttpRequest, web};
+use actix_web::web::BytesMut;
+
+use crate::DEFAULT_LIMIT;
+
+/// Pool of payload buffers that are reused across requests.
+///
+/// When registered as app data, [`CborBody`](struct.CborBody.html) and the `Cbor<T>` extractor
+/// take their buffer from the pool and return it once the payload was decoded, instead of
+/// allocating a new one for every request. The pool is shared between clones, so it can be
+/// created once and registered with every worker.
+///
+/// # Example
+/// ```
+/// use actix_cbor::CborBufferPool;
+/// use actix_web::App;
+///
+/// let pool = CborBufferPool::new(64);
+/// let app = App::new().app_data(pool.clone());
+/// ```
+#[derive(Clone)]
+pub struct CborBufferPool {
+    buffers: Arc<Mutex<Vec<BytesMut>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+impl CborBufferPool {
+    /// Create a pool that keeps up to `max_buffers` idle buffers.
+    pub fn new(max_buffers: usize) -> Self {
+        CborBufferPool {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+            max_capacity: DEFAULT_LIMIT,
+        }
+    }
+
+    /// Change the capacity of the largest buffer that is returned to the pool. Larger buffers are
+    /// dropped after use, so that a few large payloads don't hold on to memory. By default this is
+    /// 32kB, the default payload limit.
+    pub fn max_capacity(mut self, capacity: usize) -> Self {
+        self.max_capacity = capacity;
+        self
+    }
+
+    /// Number of idle buffers in the pool.
+    pub fn idle(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Take a buffer from the pool, or allocate one, with room for at least `capacity` bytes.
+    pub(crate) fn get(&self, capacity: usize) -> BytesMut {
+        let buf = self.buffers.lock().unwrap().pop();
+
+        match buf {
+            Some(mut buf) => {
+                buf.reserve(capacity);
+                buf
+            }
+            None => BytesMut::with_capacity(capacity),
+        }
+    }
+
+    /// Return a buffer to the pool, unless it is full or the buffer is too large to keep.
+    pub(crate) fn put(&self, mut buf: BytesMut) {
+        if buf.capacity() > self.max_capacity {
+            return;
+        }
+
+        buf.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    /// Extract the pool from app data. Check both `CborBufferPool` and `Data<CborBufferPool>`, in
+    /// that order.
+    pub(crate) fn from_req(req: &HttpRequest) -> Option<Self> {
+        req.app_data::<Self>()
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+            .cloned()
+    }
+}
+
+impl fmt::Debug for CborBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CborBufferPool")
+            .field("idle", &self.idle())
+            .field("max_buffers", &self.max_buffers)
+            .field("max_capacity", &self.max_capacity)
+            .finish()
+    }
+}
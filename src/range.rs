@@ -0,0 +1,64 @@
+use std::ops::Range;
+
+use actix_http::http::Method;
+use actix_http::http::header::{IF_RANGE, RANGE};
+use actix_web::HttpRequest;
+
+/// Byte range of a body of `len` bytes requested by `req`, if it should be served.
+///
+/// `None` if the request has no `Range` header, it is malformed or asks for several ranges, or an
+/// `If-Range` header does not match the strong ETag `tag`, in which case the whole body should be
+/// sent. `Some(Err(()))` if the range can't be satisfied.
+pub(crate) fn requested(
+    req: &HttpRequest,
+    len: usize,
+    tag: Option<&str>,
+) -> Option<Result<Range<usize>, ()>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return None;
+    }
+
+    let range = req.headers().get(RANGE)?.to_str().ok()?.trim();
+
+    // only a strong ETag can validate ranges; dates are not supported
+    if let Some(if_range) = req.headers().get(IF_RANGE) {
+        if tag.is_none() || if_range.to_str().ok().map(str::trim) != tag {
+            return None;
+        }
+    }
+
+    let (unit, spec) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return None;
+    }
+
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    let range = if first.is_empty() {
+        // suffix range, the last bytes of the body
+        let suffix = last.parse::<usize>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        len.saturating_sub(suffix)..len
+    } else {
+        let first = first.parse::<usize>().ok()?;
+        let end = if last.is_empty() {
+            len
+        } else {
+            let last = last.parse::<usize>().ok()?;
+            if last < first {
+                return None;
+            }
+            last.saturating_add(1).min(len)
+        };
+
+        if first >= len {
+            return Some(Err(()));
+        }
+        first..end
+    };
+
+    Some(Ok(range))
+}
@@ -0,0 +1,258 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, web};
+use lru::LruCache;
+use serde_cbor::Value;
+
+use crate::CborPayloadError;
+
+/// Default number of nonces remembered by a [`LruReplayCache`](struct.LruReplayCache.html).
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 10_000;
+
+/// Claim key of the nonce, the CWT ID (`cti`).
+const CTI: i64 = 7;
+
+/// Claim key of the timestamp, the CWT issued at time (`iat`).
+const IAT: i64 = 6;
+
+/// Storage of the nonces seen by a [`CoseReplayGuard`](struct.CoseReplayGuard.html).
+///
+/// Implement this to share nonces between instances, e.g. in a database.
+pub trait ReplayCache: Send + Sync {
+    /// Record the `nonce` of a message tagged with the key `kid`, to be remembered until
+    /// `expires`. Return `false` if the nonce was already recorded for the key and has not
+    /// expired.
+    ///
+    /// The first byte of `nonce` is the CBOR major type of the claim, `2` for byte strings and
+    /// `3` for text strings, so that `h'616263'` and `"abc"` are different nonces.
+    fn insert(&self, kid: Option<&[u8]>, nonce: &[u8], expires: SystemTime) -> bool;
+}
+
+/// Key ID and nonce of a message.
+type NonceKey = (Option<Vec<u8>>, Vec<u8>);
+
+/// In-memory [`ReplayCache`](trait.ReplayCache.html) remembering up to a fixed number of nonces,
+/// evicting the least recently seen first.
+///
+/// A nonce that is evicted before it expires can be replayed, so the capacity should cover the
+/// number of messages accepted within the max age of a
+/// [`CoseReplayGuard`](struct.CoseReplayGuard.html).
+pub struct LruReplayCache {
+    entries: Mutex<LruCache<NonceKey, SystemTime>>,
+}
+
+impl LruReplayCache {
+    /// Create a cache remembering up to `capacity` nonces.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0, as a cache that remembers nothing would accept every replay.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity)
+            .expect("LruReplayCache capacity must be at least 1");
+
+        LruReplayCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl Default for LruReplayCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_CACHE_CAPACITY)
+    }
+}
+
+impl ReplayCache for LruReplayCache {
+    fn insert(&self, kid: Option<&[u8]>, nonce: &[u8], expires: SystemTime) -> bool {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (kid.map(<[u8]>::to_vec), nonce.to_vec());
+
+        match entries.get(&key) {
+            Some(&seen) if seen > SystemTime::now() => false,
+            _ => {
+                entries.put(key, expires);
+                true
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LruReplayCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        f.debug_struct("LruReplayCache")
+            .field("len", &entries.len())
+            .field("capacity", &entries.cap())
+            .finish()
+    }
+}
+
+/// Replay protection for [`CoseMac0`](struct.CoseMac0.html) requests, registered as app data.
+///
+/// Once the tag of a message is verified, its payload must be a map with a nonce and a
+/// timestamp claim, by default the CWT ID (`7`) and issued at time (`6`). The timestamp is in
+/// seconds since the Unix epoch and must be at most [`max_age`](#method.max_age) old, allowing for
+/// [`clock_skew`](#method.clock_skew) in either direction. The nonce must not have been seen
+/// before for the same key ID within that window.
+///
+/// Messages with missing claims fail with
+/// [`CborPayloadError::MissingClaim`](enum.CborPayloadError.html#variant.MissingClaim) and
+/// `400 Bad Request`, replayed ones with
+/// [`CborPayloadError::Expired`](enum.CborPayloadError.html#variant.Expired) or
+/// [`CborPayloadError::Replayed`](enum.CborPayloadError.html#variant.Replayed) and
+/// `401 Unauthorized`.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use actix_cbor::{CoseReplayGuard, LruReplayCache};
+/// use actix_web::App;
+///
+/// let guard = CoseReplayGuard::new()
+///     .nonce_claim("nonce".to_owned())
+///     .timestamp_claim("ts".to_owned())
+///     .max_age(Duration::from_secs(60))
+///     .clock_skew(Duration::from_secs(5))
+///     .cache(LruReplayCache::new(1000));
+///
+/// let app = App::new().app_data(guard);
+/// ```
+#[derive(Clone)]
+pub struct CoseReplayGuard {
+    nonce_claim: Value,
+    timestamp_claim: Value,
+    max_age: Duration,
+    clock_skew: Duration,
+    cache: Arc<dyn ReplayCache>,
+}
+
+impl Default for CoseReplayGuard {
+    fn default() -> Self {
+        CoseReplayGuard {
+            nonce_claim: CTI.into(),
+            timestamp_claim: IAT.into(),
+            max_age: Duration::from_secs(300),
+            clock_skew: Duration::from_secs(30),
+            cache: Arc::new(LruReplayCache::default()),
+        }
+    }
+}
+
+impl CoseReplayGuard {
+    /// Create a guard with the CWT claims, a max age of 5 minutes, 30 seconds of clock skew and
+    /// an [`LruReplayCache`](struct.LruReplayCache.html) of the default capacity.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Change the map key of the nonce claim, a byte or text string. By default this is `7`.
+    pub fn nonce_claim(mut self, key: impl Into<Value>) -> Self {
+        self.nonce_claim = key.into();
+        self
+    }
+
+    /// Change the map key of the timestamp claim. By default this is `6`.
+    pub fn timestamp_claim(mut self, key: impl Into<Value>) -> Self {
+        self.timestamp_claim = key.into();
+        self
+    }
+
+    /// Change how old a timestamp may be. By default this is 5 minutes.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Change how far the clocks of clients and server may differ. By default this is 30
+    /// seconds.
+    pub fn clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Change where nonces are remembered.
+    pub fn cache(mut self, cache: impl ReplayCache + 'static) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+
+    /// Check the claims of an authenticated `payload` tagged with the key `kid`.
+    pub(crate) fn check(&self, kid: Option<&[u8]>, payload: &Value) -> Result<(), CborPayloadError> {
+        let claims = match payload {
+            Value::Map(claims) => claims,
+            _ => return Err(CborPayloadError::MissingClaim),
+        };
+
+        let (major, nonce) = match claims.get(&self.nonce_claim) {
+            Some(Value::Bytes(nonce)) => (2, nonce.as_slice()),
+            Some(Value::Text(nonce)) => (3, nonce.as_bytes()),
+            _ => return Err(CborPayloadError::MissingClaim),
+        };
+        let mut typed_nonce = Vec::with_capacity(nonce.len() + 1);
+        typed_nonce.push(major);
+        typed_nonce.extend_from_slice(nonce);
+
+        let issued = match claims.get(&self.timestamp_claim) {
+            Some(&Value::Integer(secs)) => u64::try_from(secs)
+                .ok()
+                .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs))),
+            Some(&Value::Float(secs)) if secs.is_finite() && secs >= 0.0 => {
+                Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .and_then(|secs| UNIX_EPOCH.checked_add(secs))
+            }
+            _ => return Err(CborPayloadError::MissingClaim),
+        };
+        let issued = match issued {
+            Some(issued) => issued,
+            None => return Err(CborPayloadError::Expired),
+        };
+        // a window too large to represent never expires
+        let expires = saturating_add(issued, self.max_age.saturating_add(self.clock_skew));
+
+        let now = SystemTime::now();
+        if expires < now || issued.duration_since(now).is_ok_and(|ahead| ahead > self.clock_skew) {
+            return Err(CborPayloadError::Expired);
+        }
+
+        if self.cache.insert(kid, &typed_nonce, expires) {
+            Ok(())
+        } else {
+            Err(CborPayloadError::Replayed)
+        }
+    }
+
+    /// Extract the guard from app data. Check both `CoseReplayGuard` and
+    /// `Data<CoseReplayGuard>`, in that order.
+    pub(crate) fn from_req(req: &HttpRequest) -> Option<&Self> {
+        req.app_data::<Self>()
+            .or_else(|| req.app_data::<web::Data<Self>>().map(|d| d.get_ref()))
+    }
+}
+
+/// `time + duration`, or the latest time that can be represented if that overflows.
+fn saturating_add(time: SystemTime, mut duration: Duration) -> SystemTime {
+    loop {
+        if let Some(time) = time.checked_add(duration) {
+            return time;
+        }
+        duration /= 2;
+    }
+}
+
+impl fmt::Debug for CoseReplayGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoseReplayGuard")
+            .field("nonce_claim", &self.nonce_claim)
+            .field("timestamp_claim", &self.timestamp_claim)
+            .field("max_age", &self.max_age)
+            .field("clock_skew", &self.clock_skew)
+            .finish()
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use actix_http::Response;
+use actix_http::http::StatusCode;
+use actix_web::{HttpRequest, Responder, web};
+use actix_web::web::Bytes;
+use futures_channel::mpsc;
+use futures_util::future::{FutureExt, LocalBoxFuture, ready};
+use futures_util::sink::Sink;
+use futures_util::stream::{self, StreamExt};
+use futures_util::task::{self as task, ArcWake};
+use serde::Serialize;
+
+use crate::{CborConfig, CborResponseError};
+
+/// Size of the chunks the body is sent in, and the largest body that is sent with a
+/// `Content-Length` instead of chunked encoding.
+const DEFAULT_CHUNK_SIZE: usize = 16_384;
+
+/// Number of encoded chunks that may be waiting to be sent before serialization is paused.
+const CHANNEL_CAPACITY: usize = 2;
+
+/// How long serialization may be paused waiting for the client before the response is aborted.
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Responder for CBOR encoded data that is serialized while the response is being sent.
+///
+/// Unlike [`Cbor`](struct.Cbor.html), the encoded body is never held in memory as a whole:
+/// serialization runs on the blocking thread pool and writes into a bounded channel that feeds a
+/// chunked response, so at most a few chunks are buffered at a time. Bodies that fit in a single
+/// chunk are sent with a `Content-Length` as usual. As the value is moved to the pool, it is only a
+/// `Responder` for `T: Send + 'static`.
+///
+/// The price is a thread of the blocking pool, which is shared with `web::block`, for as long as
+/// the client takes to read the response. To keep slow clients from using up the pool, the
+/// response is aborted once serialization was paused for longer than the
+/// [`send_timeout`](#method.send_timeout).
+///
+/// The [`response_limit`](struct.CborConfig.html#method.response_limit) of the
+/// [`CborConfig`](struct.CborConfig.html) applies as well. If the limit is exceeded after the first
+/// chunk was sent, the response is aborted.
+///
+/// # Example
+/// ```
+/// use actix_cbor::CborStreamed;
+/// use actix_web::get;
+///
+/// #[get("/export")]
+/// pub async fn export() -> CborStreamed<Vec<u64>> {
+///     CborStreamed::new((0..1_000_000).collect())
+/// }
+/// ```
+pub struct CborStreamed<T> {
+    value: T,
+    chunk_size: usize,
+    send_timeout: Duration,
+}
+
+impl<T> CborStreamed<T> {
+    /// Create a streamed responder for `value`.
+    pub fn new(value: T) -> Self {
+        CborStreamed {
+            value,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+        }
+    }
+
+    /// Change the size of the chunks the body is sent in. By default chunks are 16kB.
+    ///
+    /// Bodies of up to one chunk are sent with a `Content-Length`.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Change how long serialization may wait for the client to read buffered chunks before the
+    /// response is aborted. By default this is 30 seconds.
+    pub fn send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = timeout;
+        self
+    }
+
+    /// Deconstruct to an inner value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> fmt::Debug for CborStreamed<T> where T: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CborStreamed: {:?}", self.value)
+    }
+}
+
+impl<T> Responder for CborStreamed<T> where T: Serialize + Send + 'static {
+    type Error = CborResponseError;
+    type Future = LocalBoxFuture<'static, Result<Response, Self::Error>>;
+
+    fn respond_to(self, req: &HttpRequest) -> Self::Future {
+        let config = CborConfig::from_req(req);
+        let limit = config.response_limit_for(req);
+        let status = config.response_limit_status;
+
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let writer = ChannelWriter {
+            tx,
+            buf: Vec::with_capacity(self.chunk_size),
+            chunk_size: self.chunk_size,
+            written: 0,
+            limit,
+            status,
+            timeout: self.send_timeout,
+            timed_out: false,
+        };
+        let value = self.value;
+
+        actix_rt::spawn(async move {
+            let _ = web::block(move || {
+                writer.encode(&value);
+                Ok::<_, ()>(())
+            })
+                .await;
+        });
+
+        async move {
+            match rx.next().await {
+                Some(Chunk::Complete(body)) => Ok(Response::build(StatusCode::OK)
+                    .content_type("application/cbor")
+                    .body(body)),
+                Some(Chunk::Partial(first)) => {
+                    // the body is complete once the last chunk arrives, the channel closing
+                    // before that means serialization was aborted
+                    let rest = rx
+                        .map(Some)
+                        .chain(stream::once(ready(None)))
+                        .scan(false, |complete, chunk| {
+                            ready(match chunk {
+                                Some(Chunk::Partial(bytes)) => Some(Ok(bytes)),
+                                Some(Chunk::Complete(bytes)) => {
+                                    *complete = true;
+                                    Some(Ok(bytes))
+                                }
+                                Some(Chunk::Error(e)) => Some(Err(e)),
+                                None if *complete => None,
+                                None => Some(Err(stopped())),
+                            })
+                        });
+
+                    Ok(Response::build(StatusCode::OK)
+                        .content_type("application/cbor")
+                        .streaming(stream::once(ready(Ok(first))).chain(rest)))
+                }
+                Some(Chunk::Error(e)) => Err(e),
+                None => Err(stopped()),
+            }
+        }
+            .boxed_local()
+    }
+}
+
+/// Error for a body whose serialization stopped before it was complete.
+fn stopped() -> CborResponseError {
+    <serde_cbor::Error as serde::ser::Error>::custom("CBOR serializer stopped unexpectedly").into()
+}
+
+enum Chunk {
+    /// The last chunk of the body, or the whole body when it fits in a single chunk
+    Complete(Bytes),
+    /// Part of a body that is sent in several chunks
+    Partial(Bytes),
+    Error(CborResponseError),
+}
+
+/// Writer that sends the encoded body through a bounded channel in chunks, blocking while the
+/// channel is full for at most `timeout`.
+struct ChannelWriter {
+    tx: mpsc::Sender<Chunk>,
+    buf: Vec<u8>,
+    chunk_size: usize,
+    written: usize,
+    limit: Option<usize>,
+    status: StatusCode,
+    timeout: Duration,
+    timed_out: bool,
+}
+
+impl ChannelWriter {
+    fn encode<T: Serialize>(mut self, value: &T) {
+        let res = serde_cbor::to_writer(&mut self, value);
+
+        // the client is not reading, so it won't read an error either
+        if self.timed_out {
+            return;
+        }
+
+        let last = match res {
+            Ok(()) => Chunk::Complete(Bytes::from(std::mem::take(&mut self.buf))),
+            Err(_) if self.over_limit() => Chunk::Error(CborResponseError::Overflow {
+                size: self.written,
+                limit: self.limit.unwrap_or_default(),
+                status: self.status,
+            }),
+            Err(e) => Chunk::Error(e.into()),
+        };
+
+        // the receiver is gone if the client disconnected, nothing left to do then
+        let _ = self.send(last);
+    }
+
+    fn over_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.written > limit)
+    }
+
+    /// Send a chunk, parking the thread while the channel is full.
+    fn send(&mut self, chunk: Chunk) -> io::Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let waker = task::waker(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match Pin::new(&mut self.tx).poll_ready(&mut cx) {
+                Poll::Ready(Ok(())) => break,
+                Poll::Ready(Err(_)) => return Err(dropped()),
+                Poll::Pending => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        self.timed_out = true;
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "response was not read in time",
+                        ));
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+
+        Pin::new(&mut self.tx).start_send(chunk).map_err(|_| dropped())
+    }
+}
+
+fn dropped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "response was dropped")
+}
+
+/// Waker that unparks the serializing thread once the channel has room again.
+struct ThreadWaker(Thread);
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len();
+        if self.over_limit() {
+            return Err(io::Error::other("response limit exceeded"));
+        }
+
+        self.buf.extend_from_slice(buf);
+
+        // only start sending once there is more than a chunk, so small bodies stay complete
+        while self.buf.len() > self.chunk_size {
+            let rest = self.buf.split_off(self.chunk_size);
+            let chunk = std::mem::replace(&mut self.buf, rest);
+            self.send(Chunk::Partial(Bytes::from(chunk)))?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
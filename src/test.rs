@@ -0,0 +1,245 @@
+//! Helpers for testing CBOR endpoints.
+//!
+//! Enabled with the `test-utils` feature.
+//!
+//! # Example
+//! ```
+//! use actix_cbor::{assert_cbor_eq, Cbor};
+//! use actix_cbor::test::{read_cbor_body, CborTestRequestExt};
+//! use actix_web::{test, web, App};
+//!
+//! # #[actix_rt::main]
+//! # async fn main() {
+//! let mut app = test::init_service(
+//!     App::new().route("/", web::post().to(|n: Cbor<u32>| async move { Cbor(n.0 + 1) }))
+//! ).await;
+//!
+//! let req = test::TestRequest::post().uri("/").set_cbor(&41u32).to_request();
+//! let res = test::call_service(&mut app, req).await;
+//! let body = test::read_body(res).await;
+//!
+//! assert_cbor_eq!(body, 42u32);
+//! # }
+//! ```
+
+use actix_http::body::MessageBody;
+use actix_http::Response;
+use actix_http::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::dev::ServiceResponse;
+use actix_web::test::{load_stream, read_body, TestRequest};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_cbor::Value;
+
+/// Extension trait for building CBOR requests with `actix_web::test::TestRequest`.
+pub trait CborTestRequestExt {
+    /// Serialize `data` to CBOR and set it as the request payload. The `Content-Type` header is
+    /// set to `application/cbor` and `Content-Length` to the encoded size.
+    fn set_cbor<T: Serialize>(self, data: &T) -> Self;
+}
+
+impl CborTestRequestExt for TestRequest {
+    fn set_cbor<T: Serialize>(self, data: &T) -> Self {
+        let bytes = serde_cbor::to_vec(data)
+            .expect("Failed to serialize test data to CBOR");
+
+        self.header(CONTENT_TYPE, "application/cbor")
+            .header(CONTENT_LENGTH, bytes.len())
+            .set_payload(bytes)
+    }
+}
+
+/// Read the body of a `ServiceResponse` and deserialize it from CBOR.
+///
+/// Panics if the body is not valid CBOR for `T`.
+pub async fn read_cbor_body<T, B>(res: ServiceResponse<B>) -> T
+    where
+        B: MessageBody + Unpin,
+        T: DeserializeOwned,
+{
+    let body = read_body(res).await;
+
+    serde_cbor::from_slice(&body)
+        .unwrap_or_else(|e| panic!("read_cbor_body failed during deserialization: {}", e))
+}
+
+/// Read the body of a `Response`, such as one produced by a `Responder`, and deserialize it from
+/// CBOR.
+///
+/// Panics if the body can not be read or is not valid CBOR for `T`.
+pub async fn read_cbor_response<T>(mut res: Response) -> T
+    where
+        T: DeserializeOwned,
+{
+    let body = load_stream(res.take_body())
+        .await
+        .unwrap_or_else(|e| panic!("read_cbor_response failed to read the body: {}", e));
+
+    serde_cbor::from_slice(&body)
+        .unwrap_or_else(|e| panic!("read_cbor_response failed during deserialization: {}", e))
+}
+
+/// Asserts that an encoded CBOR body is equal to a serializable value.
+///
+/// The comparison is done on the decoded data model, so map ordering and integer widths do not
+/// matter. On mismatch, both sides are printed in CBOR diagnostic notation along with a line diff.
+///
+/// ```
+/// use actix_cbor::assert_cbor_eq;
+///
+/// let body = serde_cbor::to_vec(&(1, "two")).unwrap();
+/// assert_cbor_eq!(body, (1, "two"));
+/// ```
+#[macro_export]
+macro_rules! assert_cbor_eq {
+    ($body:expr, $expected:expr $(,)?) => {
+        $crate::test::assert_cbor_eq_impl(::std::convert::AsRef::<[u8]>::as_ref(&$body), &$expected)
+    };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_cbor_eq_impl<T: Serialize + ?Sized>(body: &[u8], expected: &T) {
+    let actual: Value = serde_cbor::from_slice(body)
+        .unwrap_or_else(|e| panic!("assert_cbor_eq: body is not valid CBOR: {}", e));
+    let expected = serde_cbor::value::to_value(expected)
+        .unwrap_or_else(|e| panic!("assert_cbor_eq: failed to encode expected value: {}", e));
+
+    if actual != expected {
+        let actual = diagnostic_pretty(&actual);
+        let expected = diagnostic_pretty(&expected);
+
+        panic!(
+            "assertion failed: `(body == expected)`\n  body: {}\nexpected: {}\n\ndiff (- expected, + body):\n{}",
+            actual,
+            expected,
+            line_diff(&expected, &actual),
+        );
+    }
+}
+
+/// Render a CBOR value in diagnostic notation (RFC 8949, section 8).
+pub fn diagnostic(value: &Value) -> String {
+    let mut out = String::new();
+    write_diagnostic(&mut out, value, None);
+    out
+}
+
+/// Render a CBOR value in diagnostic notation, one array item or map entry per line.
+pub fn diagnostic_pretty(value: &Value) -> String {
+    let mut out = String::new();
+    write_diagnostic(&mut out, value, Some(0));
+    out
+}
+
+fn write_diagnostic(out: &mut String, value: &Value, indent: Option<usize>) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        Value::Float(f) => {
+            if f.is_nan() {
+                out.push_str("NaN")
+            } else if f.is_infinite() {
+                out.push_str(if *f > 0.0 { "Infinity" } else { "-Infinity" })
+            } else {
+                out.push_str(&format!("{:?}", f))
+            }
+        }
+        Value::Bytes(bytes) => {
+            out.push_str("h'");
+            for b in bytes {
+                out.push_str(&format!("{:02x}", b));
+            }
+            out.push('\'');
+        }
+        Value::Text(text) => out.push_str(&format!("{:?}", text)),
+        Value::Tag(tag, inner) => {
+            out.push_str(&format!("{}(", tag));
+            write_diagnostic(out, inner, indent);
+            out.push(')');
+        }
+        Value::Array(items) => {
+            write_seq(out, '[', ']', items.iter(), indent, |out, item, indent| {
+                write_diagnostic(out, item, indent)
+            })
+        }
+        Value::Map(entries) => {
+            write_seq(out, '{', '}', entries.iter(), indent, |out, (k, v), indent| {
+                write_diagnostic(out, k, indent);
+                out.push_str(": ");
+                write_diagnostic(out, v, indent);
+            })
+        }
+        Value::__Hidden => unreachable!(),
+    }
+}
+
+fn write_seq<I, F>(out: &mut String, open: char, close: char, items: I, indent: Option<usize>, f: F)
+    where
+        I: ExactSizeIterator,
+        F: Fn(&mut String, I::Item, Option<usize>),
+{
+    out.push(open);
+    let len = items.len();
+
+    match indent {
+        Some(level) if len > 0 => {
+            for (i, item) in items.enumerate() {
+                out.push('\n');
+                out.push_str(&"  ".repeat(level + 1));
+                f(out, item, Some(level + 1));
+                if i + 1 < len {
+                    out.push(',');
+                }
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(level));
+        }
+        _ => {
+            for (i, item) in items.enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                f(out, item, None);
+            }
+        }
+    }
+
+    out.push(close);
+}
+
+/// Minimal line diff based on the longest common subsequence.
+fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push_str(&format!("  {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push_str(&format!("+ {}\n", new[j]));
+            j += 1;
+        } else {
+            out.push_str(&format!("- {}\n", old[i]));
+            i += 1;
+        }
+    }
+
+    out
+}
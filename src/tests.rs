@@ -1,7 +1,9 @@
 use super::*;
+use crate::assert_cbor_eq;
+use crate::test::{diagnostic, read_cbor_body, read_cbor_response, CborTestRequestExt};
 use actix_web::error::InternalError;
-use actix_web::http::header::{self, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
-use actix_web::test::{load_stream, TestRequest};
+use actix_web::http::header;
+use actix_web::test::TestRequest;
 use actix_web::{HttpResponse, web};
 use actix_http::body::Body;
 
@@ -55,24 +57,17 @@ async fn test_responder() {
 
     if let Body::Bytes(b) = payload {
         assert_eq!(&encoded, b);
-
-        let decoded: MyObject = serde_cbor::from_slice(&b).unwrap();
-        assert_eq!(obj, decoded);
+        assert_cbor_eq!(b, obj);
     }
+
+    let decoded: MyObject = read_cbor_response(resp).await;
+    assert_eq!(obj, decoded);
 }
 
 #[actix_rt::test]
 async fn test_custom_error_responder() {
     let (req, mut pl) = TestRequest::default()
-        .header(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/cbor"),
-        )
-        .header(
-            header::CONTENT_LENGTH,
-            header::HeaderValue::from_static("16"),
-        )
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .app_data(CborConfig::default().limit(10).error_handler(|err, _| {
             let msg = MyObject::default();
             let resp = HttpResponse::BadRequest()
@@ -82,26 +77,17 @@ async fn test_custom_error_responder() {
         .to_http_parts();
 
     let s = Cbor::<MyObject>::from_request(&req, &mut pl).await;
-    let mut resp = Response::from_error(s.err().unwrap());
+    let resp = Response::from_error(s.err().unwrap());
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 
-    let body = load_stream(resp.take_body()).await.unwrap();
-    let msg: MyObject = serde_cbor::from_slice(&body).unwrap();
+    let msg: MyObject = read_cbor_response(resp).await;
     assert_eq!(msg.name, "test");
 }
 
 #[actix_rt::test]
 async fn test_extract() {
     let (req, mut pl) = TestRequest::default()
-        .header(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/cbor"),
-        )
-        .header(
-            header::CONTENT_LENGTH,
-            header::HeaderValue::from_static("16"),
-        )
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .to_http_parts();
 
     let s = Cbor::<MyObject>::from_request(&req, &mut pl).await.unwrap();
@@ -112,15 +98,7 @@ async fn test_extract() {
     );
 
     let (req, mut pl) = TestRequest::default()
-        .header(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/cbor"),
-        )
-        .header(
-            header::CONTENT_LENGTH,
-            header::HeaderValue::from_static("16"),
-        )
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .app_data(CborConfig::default().limit(10))
         .to_http_parts();
 
@@ -129,15 +107,7 @@ async fn test_extract() {
         .contains("Cbor payload size is bigger than allowed"));
 
     let (req, mut pl) = TestRequest::default()
-        .header(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/cbor"),
-        )
-        .header(
-            header::CONTENT_LENGTH,
-            header::HeaderValue::from_static("16"),
-        )
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .app_data(
             CborConfig::default()
                 .limit(10)
@@ -180,15 +150,7 @@ async fn test_cbor_body() {
     assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::Overflow));
 
     let (req, mut pl) = TestRequest::default()
-        .header(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/cbor"),
-        )
-        .header(
-            header::CONTENT_LENGTH,
-            header::HeaderValue::from_static("16"),
-        )
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .to_http_parts();
 
     let cbor = CborBody::<MyObject>::new(&req, &mut pl, None).await;
@@ -259,9 +221,7 @@ async fn test_with_cbor_and_bad_custom_content_type() {
 #[actix_rt::test]
 async fn test_with_config_in_data_wrapper() {
     let (req, mut pl) = TestRequest::default()
-        .header(CONTENT_TYPE, HeaderValue::from_static("application/cbor"))
-        .header(CONTENT_LENGTH, HeaderValue::from_static("16"))
-        .set_payload(get_test_bytes())
+        .set_cbor(&MyObject::default())
         .app_data(web::Data::new(CborConfig::default().limit(10)))
         .to_http_parts();
 
@@ -271,3 +231,2172 @@ async fn test_with_config_in_data_wrapper() {
     let err_str = s.err().unwrap().to_string();
     assert!(err_str.contains("Cbor payload size is bigger than allowed"));
 }
+
+#[cfg(feature = "client")]
+#[actix_rt::test]
+async fn test_client_cbor_body() {
+    use awc::test::TestResponse;
+
+    let mut res = TestResponse::default().finish();
+    let cbor = res.cbor::<MyObject>().await;
+    assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::ContentType));
+
+    let mut res = TestResponse::default()
+        .header(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/cbor"),
+        )
+        .header(
+            header::CONTENT_LENGTH,
+            header::HeaderValue::from_static("10000"),
+        )
+        .finish();
+    let cbor = res.cbor::<MyObject>().limit(100).await;
+    assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::Overflow));
+
+    let mut res = TestResponse::default()
+        .header(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/cbor"),
+        )
+        .set_payload(get_test_bytes())
+        .finish();
+    let cbor = res.cbor::<MyObject>().limit(10).await;
+    assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::Overflow));
+
+    let mut res = TestResponse::default()
+        .header(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/cbor"),
+        )
+        .header(
+            header::CONTENT_LENGTH,
+            header::HeaderValue::from_static("16"),
+        )
+        .set_payload(get_test_bytes())
+        .finish();
+    let cbor = res.cbor::<MyObject>().await;
+    assert_eq!(cbor.ok().unwrap(), MyObject::default());
+}
+
+#[cfg(feature = "client")]
+#[actix_rt::test]
+async fn test_client_send_cbor() {
+    let srv = actix_web::test::start(|| {
+        actix_web::App::new().route(
+            "/",
+            web::post().to(|obj: Cbor<MyObject>| async move { obj }),
+        )
+    });
+
+    let mut res = srv.post("/").send_cbor(&MyObject::default()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let obj = res.cbor::<MyObject>().await.unwrap();
+    assert_eq!(obj, MyObject::default());
+}
+
+#[actix_rt::test]
+async fn test_read_cbor_body() {
+    let mut app = actix_web::test::init_service(actix_web::App::new().route(
+        "/",
+        web::post().to(|obj: Cbor<MyObject>| async move { obj }),
+    ))
+        .await;
+
+    let req = TestRequest::post()
+        .uri("/")
+        .set_cbor(&MyObject::default())
+        .to_request();
+    let resp = actix_web::test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let obj: MyObject = read_cbor_body(resp).await;
+    assert_eq!(obj, MyObject::default());
+}
+
+#[test]
+fn test_diagnostic_notation() {
+    let value: serde_cbor::Value = serde_cbor::from_slice(&get_test_bytes()).unwrap();
+    assert_eq!(diagnostic(&value), r#"{"name": "test", "number": 7}"#);
+
+    let value = serde_cbor::Value::Array(vec![
+        serde_cbor::Value::Bytes(vec![0x0a, 0xff]),
+        serde_cbor::Value::Float(-1.5),
+        serde_cbor::Value::Null,
+        serde_cbor::Value::Tag(1, Box::new(serde_cbor::Value::Integer(0))),
+    ]);
+    assert_eq!(diagnostic(&value), "[h'0aff', -1.5, null, 1(0)]");
+}
+
+#[test]
+#[should_panic(expected = "-   \"number\": 8")]
+fn test_assert_cbor_eq_mismatch() {
+    let expected = MyObject {
+        number: 8,
+        ..MyObject::default()
+    };
+    assert_cbor_eq!(get_test_bytes(), expected);
+}
+
+#[cfg(feature = "multipart")]
+fn multipart_body() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"preamble\r\n--abc123\r\n");
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"metadata\"\r\n");
+    body.extend_from_slice(b"Content-Type: application/cbor\r\n\r\n");
+    body.extend_from_slice(&get_test_bytes());
+    body.extend_from_slice(b"\r\n--abc123\r\n");
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"firmware\"; filename=\"fw.bin\"\r\n");
+    body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+    body.extend_from_slice(b"\x00\x01\r\n--abc\x02\x03");
+    body.extend_from_slice(b"\r\n--abc123--\r\n");
+    body
+}
+
+#[cfg(feature = "multipart")]
+async fn read_multipart(
+    req: &HttpRequest,
+    pl: &mut Payload,
+) -> Result<(MyObject, Vec<u8>), actix_web::Error> {
+    use futures_util::{StreamExt, TryStreamExt};
+
+    let mut form = CborMultipart::from_request(req, pl).await?;
+
+    let mut field = form.try_next().await?.unwrap();
+    assert_eq!(field.name(), Some("metadata"));
+    assert!(field.is_cbor());
+    let obj = field.cbor::<MyObject>().await?;
+
+    let mut field = form.try_next().await?.unwrap();
+    assert_eq!(field.name(), Some("firmware"));
+    assert_eq!(field.filename(), Some("fw.bin"));
+    assert!(!field.is_cbor());
+    let mut file = Vec::new();
+    while let Some(chunk) = field.next().await {
+        file.extend_from_slice(&chunk?);
+    }
+
+    assert!(form.try_next().await?.is_none());
+    Ok((obj, file))
+}
+
+#[cfg(feature = "multipart")]
+#[actix_rt::test]
+async fn test_multipart() {
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+        .set_payload(multipart_body())
+        .to_http_parts();
+
+    let (obj, file) = read_multipart(&req, &mut pl).await.unwrap();
+    assert_eq!(obj, MyObject::default());
+    assert_eq!(file, b"\x00\x01\r\n--abc\x02\x03");
+
+    // the same body, delivered one byte at a time
+    let chunks = multipart_body()
+        .into_iter()
+        .map(|b| Ok(actix_web::web::Bytes::copy_from_slice(&[b])))
+        .collect::<Vec<_>>();
+    let req = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/mixed; boundary=abc123")
+        .to_http_request();
+    let stream: PayloadStream = Box::pin(futures_util::stream::iter(chunks));
+    let mut pl = Payload::Stream(stream);
+
+    let (obj, file) = read_multipart(&req, &mut pl).await.unwrap();
+    assert_eq!(obj, MyObject::default());
+    assert_eq!(file, b"\x00\x01\r\n--abc\x02\x03");
+
+    // the boundary only delimits parts at the start of a line, not in the middle of the preamble
+    let mut body = b"see --abc123\r\nfor the parts --abc123 \r\n".to_vec();
+    body.extend_from_slice(&multipart_body());
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+        .set_payload(body)
+        .to_http_parts();
+
+    let (obj, file) = read_multipart(&req, &mut pl).await.unwrap();
+    assert_eq!(obj, MyObject::default());
+    assert_eq!(file, b"\x00\x01\r\n--abc\x02\x03");
+}
+
+#[cfg(feature = "multipart")]
+#[actix_rt::test]
+async fn test_multipart_errors() {
+    use futures_util::TryStreamExt;
+
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .set_payload(multipart_body())
+        .to_http_parts();
+    assert!(CborMultipart::from_request(&req, &mut pl).await.is_err());
+
+    // part limit comes from CborConfig
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+        .set_payload(multipart_body())
+        .app_data(CborConfig::default().limit(10))
+        .to_http_parts();
+    let mut form = CborMultipart::from_request(&req, &mut pl).await.unwrap();
+    let mut field = form.try_next().await.unwrap().unwrap();
+    let res = field.cbor::<MyObject>().await;
+    assert!(cbor_eq(res.err().unwrap(), CborPayloadError::Overflow));
+
+    // truncated body
+    let mut body = multipart_body();
+    body.truncate(body.len() - 10);
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+        .set_payload(body)
+        .to_http_parts();
+    let mut form = CborMultipart::from_request(&req, &mut pl).await.unwrap();
+    form.try_next().await.unwrap().unwrap();
+    let res = form.try_next().await.unwrap().unwrap().try_next().await;
+    assert!(res.is_ok());
+    assert!(matches!(
+        form.try_next().await,
+        Err(CborPayloadError::Multipart(MultipartError::Incomplete))
+    ));
+
+    // a boundary must be followed by a line break or `--`
+    for tail in &[&b"xx"[..], b"-x", b" \tx\r\n"] {
+        let mut body = b"--abc123".to_vec();
+        body.extend_from_slice(tail);
+        body.extend_from_slice(b"\r\nbody\r\n--abc123--\r\n");
+        let (req, mut pl) = TestRequest::default()
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+            .set_payload(body)
+            .to_http_parts();
+        let mut form = CborMultipart::from_request(&req, &mut pl).await.unwrap();
+        assert!(matches!(
+            form.try_next().await,
+            Err(CborPayloadError::Multipart(MultipartError::Boundary))
+        ));
+    }
+}
+
+#[cfg(feature = "multipart")]
+#[actix_rt::test]
+async fn test_multipart_without_headers() {
+    use futures_util::TryStreamExt;
+
+    // transport padding after the boundary, then a part without headers
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "multipart/mixed; boundary=abc123")
+        .set_payload(&b"--abc123 \t\r\n\r\nraw\r\n--abc123--"[..])
+        .to_http_parts();
+    let mut form = CborMultipart::from_request(&req, &mut pl).await.unwrap();
+
+    let mut field = form.try_next().await.unwrap().unwrap();
+    assert!(field.headers().is_empty());
+    assert_eq!(field.try_next().await.unwrap().unwrap(), &b"raw"[..]);
+    assert!(field.try_next().await.unwrap().is_none());
+    assert!(form.try_next().await.unwrap().is_none());
+}
+
+#[cfg(all(feature = "multipart", feature = "compress"))]
+#[actix_rt::test]
+async fn test_multipart_compressed_limits() {
+    use futures_util::TryStreamExt;
+    use std::io::Write;
+
+    // a file part of ~64kB of zeros compresses to a few hundred bytes
+    let mut body = b"--abc123\r\nContent-Type: application/octet-stream\r\n\r\n".to_vec();
+    body.extend_from_slice(&[0; 65_536]);
+    body.extend_from_slice(b"\r\n--abc123--\r\n");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&body).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let read = |config: CborConfig| {
+        let (req, mut pl) = TestRequest::default()
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=abc123")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .set_payload(compressed.clone())
+            .app_data(config)
+            .to_http_parts();
+        async move {
+            let mut form = CborMultipart::from_request(&req, &mut pl).await.unwrap();
+            let mut field = form.try_next().await?.unwrap();
+            while field.try_next().await?.is_some() {}
+            form.try_next().await.map(|_| ())
+        }
+    };
+
+    // the decompressed body as a whole is limited
+    let res = read(CborConfig::default()).await;
+    assert!(matches!(res, Err(CborPayloadError::Overflow)));
+    assert!(read(CborConfig::default().limit(100_000)).await.is_ok());
+
+    // as is the compressed body
+    let res = read(CborConfig::default().limit(100_000).compressed_limit(64)).await;
+    assert!(matches!(res, Err(CborPayloadError::CompressedOverflow)));
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Upload {
+    data: Vec<u8>,
+}
+
+#[actix_rt::test]
+async fn test_typed_config() {
+    let upload = Upload { data: vec![0; 64] };
+    let config = CborConfig::default()
+        .limit(10)
+        .for_type::<Upload>(CborConfig::default().limit(1024));
+
+    // the typed config applies to `Upload` only, everything else uses the untyped config
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .app_data(config.clone())
+        .to_http_parts();
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert_eq!(s.unwrap().into_inner(), upload);
+
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&MyObject::default())
+        .app_data(config.clone())
+        .to_http_parts();
+    let s = Cbor::<MyObject>::from_request(&req, &mut pl).await;
+    assert!(s.is_err());
+
+    // `Data` wrapped config
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .app_data(web::Data::new(
+            CborConfig::default().for_type::<Upload>(CborConfig::default().limit(10)),
+        ))
+        .to_http_parts();
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert!(s.is_err());
+}
+
+#[actix_rt::test]
+async fn test_config_resolution_order() {
+    let upload = Upload { data: vec![0; 64] };
+    let typed = CborConfig::default().for_type::<Upload>(CborConfig::default().limit(1024));
+
+    // request extensions take precedence over app data
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .app_data(typed.clone())
+        .to_http_parts();
+    req.extensions_mut().insert(CborConfig::default().limit(10));
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert!(s.is_err());
+
+    // typed config takes precedence over untyped config in the request extensions
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .to_http_parts();
+    req.extensions_mut().insert(typed.clone().limit(10));
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert!(s.is_ok());
+
+    // the innermost config wins, even if an outer one has a config for the type
+    let app = |outer: CborConfig, inner: CborConfig| {
+        actix_web::test::init_service(
+            actix_web::App::new().app_data(outer).service(
+                web::resource("/")
+                    .app_data(inner)
+                    .route(web::post().to(|u: Cbor<Upload>| async move { u })),
+            ),
+        )
+    };
+    let req = || TestRequest::post().uri("/").set_cbor(&upload).to_request();
+
+    let mut srv = app(typed.clone(), CborConfig::default().limit(10)).await;
+    let resp = actix_web::test::call_service(&mut srv, req()).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // typed config takes precedence over untyped config at the same level
+    let mut srv = app(CborConfig::default().limit(10), typed.clone().limit(10)).await;
+    let resp = actix_web::test::call_service(&mut srv, req()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_async_error_handler() {
+    use std::sync::{Arc, Mutex};
+
+    let quarantine = Arc::new(Mutex::new(Vec::new()));
+    let stored = quarantine.clone();
+    let config = CborConfig::default().error_handler_async(move |err, req, body| {
+        let stored = stored.clone();
+        async move {
+            stored.lock().unwrap().push((req.path().to_owned(), body));
+            InternalError::from_response(err, HttpResponse::UnprocessableEntity().finish()).into()
+        }
+    });
+
+    // malformed body is passed to the handler
+    let (req, mut pl) = TestRequest::with_uri("/upload")
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .set_payload(&b"\xa1\x64name"[..])
+        .app_data(config.clone())
+        .to_http_parts();
+    let s = Cbor::<MyObject>::from_request(&req, &mut pl).await;
+    let resp = Response::from_error(s.err().unwrap());
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // the body is not available when it was never read
+    let (req, mut pl) = TestRequest::with_uri("/upload")
+        .header(header::CONTENT_TYPE, "text/plain")
+        .set_payload(get_test_bytes())
+        .app_data(config)
+        .to_http_parts();
+    let s = Cbor::<MyObject>::from_request(&req, &mut pl).await;
+    assert!(s.is_err());
+
+    let quarantine = quarantine.lock().unwrap();
+    assert_eq!(quarantine.len(), 2);
+    assert_eq!(quarantine[0].0, "/upload");
+    assert_eq!(quarantine[0].1.as_deref(), Some(&b"\xa1\x64name"[..]));
+    assert_eq!(quarantine[1].1, None);
+}
+
+#[actix_rt::test]
+async fn test_error_handler_replaces_async_handler() {
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&MyObject::default())
+        .app_data(
+            CborConfig::default()
+                .limit(10)
+                .error_handler_async(|err, _, _| async move { err.into() })
+                .error_handler(|_, _| CborPayloadError::ContentType.into()),
+        )
+        .to_http_parts();
+    let s = Cbor::<MyObject>::from_request(&req, &mut pl).await;
+    assert!(format!("{}", s.err().unwrap()).contains("Content type error"));
+}
+
+#[actix_rt::test]
+async fn test_default_limit() {
+    let upload = Upload { data: vec![0; DEFAULT_LIMIT] };
+
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .to_http_parts();
+    let cbor = CborBody::<Upload>::new(&req, &mut pl, None).await;
+    assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::Overflow));
+
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .to_http_parts();
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert!(s.is_err());
+
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .to_http_parts();
+    let cbor = CborBody::<Upload>::new(&req, &mut pl, None)
+        .limit(DEFAULT_LIMIT * 2)
+        .await;
+    assert_eq!(cbor.unwrap(), upload);
+}
+
+#[cfg(feature = "compress")]
+fn gzip_request(upload: &Upload, config: CborConfig, content_length: bool) -> (HttpRequest, Payload) {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&serde_cbor::to_vec(upload).unwrap()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut req = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .header(header::CONTENT_ENCODING, "gzip");
+    if content_length {
+        req = req.header(header::CONTENT_LENGTH, compressed.len());
+    }
+
+    req.set_payload(compressed)
+        .app_data(config)
+        .to_http_parts()
+}
+
+#[cfg(feature = "compress")]
+#[actix_rt::test]
+async fn test_compressed_limits() {
+    // ~64kB of zeros compresses to a few hundred bytes
+    let upload = Upload { data: vec![0; 65_536] };
+
+    let (req, mut pl) = gzip_request(&upload, CborConfig::default().limit(100_000), true);
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert_eq!(s.unwrap().into_inner(), upload);
+
+    // decompressed size is checked against `limit`
+    let (req, mut pl) = gzip_request(&upload, CborConfig::default(), false);
+    let cbor = CborBody::<Upload>::new(&req, &mut pl, None).await;
+    assert!(cbor_eq(cbor.err().unwrap(), CborPayloadError::Overflow));
+
+    let (req, mut pl) = gzip_request(&upload, CborConfig::default(), true);
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    let resp = Response::from_error(s.err().unwrap());
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // compressed size is checked against `compressed_limit`, both by content length and while
+    // streaming
+    let config = CborConfig::default().limit(100_000).compressed_limit(64);
+    let (req, mut pl) = gzip_request(&upload, config.clone(), true);
+    let s = Cbor::<Upload>::from_request(&req, &mut pl).await;
+    assert!(format!("{}", s.err().unwrap()).contains("Compressed cbor payload size"));
+
+    let (req, mut pl) = gzip_request(&upload, config, false);
+    let cbor = CborBody::<Upload>::new(&req, &mut pl, None)
+        .limit(100_000)
+        .compressed_limit(64)
+        .await;
+    assert!(matches!(cbor, Err(CborPayloadError::CompressedOverflow)));
+}
+
+#[actix_rt::test]
+async fn test_response_limit() {
+    let obj = MyObject::default();
+
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(get_test_bytes().len()))
+        .to_http_request();
+    let resp = Cbor(obj.clone()).respond_to(&req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(10))
+        .to_http_request();
+    let e = Cbor(obj.clone()).respond_to(&req).await.err().unwrap();
+    assert!(matches!(e, CborResponseError::Overflow { size, limit: 10, .. } if size == get_test_bytes().len()));
+    assert_eq!(Response::from_error(e.into()).status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let req = TestRequest::default()
+        .app_data(
+            CborConfig::default()
+                .response_limit(10)
+                .response_limit_status(StatusCode::INSUFFICIENT_STORAGE),
+        )
+        .to_http_request();
+    let e = Cbor(obj).respond_to(&req).await.err().unwrap();
+    assert_eq!(Response::from_error(e.into()).status(), StatusCode::INSUFFICIENT_STORAGE);
+}
+
+#[actix_rt::test]
+async fn test_response_limit_header() {
+    let config = CborConfig::default()
+        .response_limit(100)
+        .response_limit_header(header::HeaderName::from_static("x-max-response-size"));
+
+    // the client hint lowers the limit
+    let req = TestRequest::default()
+        .header("x-max-response-size", "10")
+        .app_data(config.clone())
+        .to_http_request();
+    let e = Cbor(MyObject::default()).respond_to(&req).await.err().unwrap();
+    assert!(matches!(e, CborResponseError::Overflow { limit: 10, .. }));
+
+    // but can't raise it
+    let req = TestRequest::default()
+        .header("x-max-response-size", "1000")
+        .app_data(CborConfig::default().response_limit(10).response_limit_header(
+            header::HeaderName::from_static("x-max-response-size"),
+        ))
+        .to_http_request();
+    assert!(Cbor(MyObject::default()).respond_to(&req).await.is_err());
+
+    // malformed hints are ignored
+    let req = TestRequest::default()
+        .header("x-max-response-size", "lots")
+        .app_data(config)
+        .to_http_request();
+    assert!(Cbor(MyObject::default()).respond_to(&req).await.is_ok());
+}
+
+#[actix_rt::test]
+async fn test_streamed_responder() {
+    let req = TestRequest::default().to_http_request();
+
+    // small bodies are sent in one piece
+    let resp = CborStreamed::new(MyObject::default()).respond_to(&req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        header::HeaderValue::from_static("application/cbor")
+    );
+    assert!(matches!(resp.body(), actix_http::body::ResponseBody::Body(Body::Bytes(_))));
+    assert_eq!(read_cbor_response::<MyObject>(resp).await, MyObject::default());
+
+    // larger ones are chunked
+    let data: Vec<u64> = (0..10_000).collect();
+    let resp = CborStreamed::new(data.clone()).chunk_size(1024).respond_to(&req).await.unwrap();
+    assert!(matches!(resp.body(), actix_http::body::ResponseBody::Body(Body::Message(_))));
+    assert_eq!(read_cbor_response::<Vec<u64>>(resp).await, data);
+}
+
+#[actix_rt::test]
+async fn test_streamed_response_limit() {
+    let data: Vec<u64> = (0..10_000).collect();
+
+    // over the limit before the first chunk is sent
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(100))
+        .to_http_request();
+    let e = CborStreamed::new(data.clone()).respond_to(&req).await.err().unwrap();
+    assert!(matches!(e, CborResponseError::Overflow { limit: 100, .. }));
+
+    // over the limit after the first chunk, the body is aborted
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(4096))
+        .to_http_request();
+    let mut resp = CborStreamed::new(data).chunk_size(1024).respond_to(&req).await.unwrap();
+    assert!(actix_web::test::load_stream(resp.take_body()).await.is_err());
+}
+
+#[actix_rt::test]
+async fn test_streamed_send_timeout() {
+    let req = TestRequest::default().to_http_request();
+    let data: Vec<u64> = (0..10_000).collect();
+
+    // a client that stops reading has its response aborted instead of holding the thread
+    let mut resp = CborStreamed::new(data)
+        .chunk_size(1024)
+        .send_timeout(std::time::Duration::from_millis(20))
+        .respond_to(&req)
+        .await
+        .unwrap();
+    actix_rt::time::delay_for(std::time::Duration::from_millis(200)).await;
+    assert!(actix_web::test::load_stream(resp.take_body()).await.is_err());
+}
+
+/// Payload stream of `chunks` that never ends, so reading it in full hangs.
+fn endless_payload(chunks: Vec<&'static [u8]>) -> Payload {
+    use futures_util::StreamExt;
+
+    let chunks = chunks
+        .into_iter()
+        .map(|chunk| Ok::<_, actix_http::error::PayloadError>(web::Bytes::from_static(chunk)));
+    let stream: PayloadStream = Box::pin(
+        futures_util::stream::iter(chunks).chain(futures_util::stream::pending()),
+    );
+    Payload::Stream(stream)
+}
+
+async fn validate_chunks(chunks: Vec<&'static [u8]>) -> Result<serde_cbor::Value, CborPayloadError> {
+    let req = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .to_http_request();
+    let stream: PayloadStream = Box::pin(futures_util::stream::iter(
+        chunks.into_iter().map(|chunk| Ok(web::Bytes::from_static(chunk))),
+    ));
+    let mut pl = Payload::Stream(stream);
+    CborBody::new(&req, &mut pl, None).incremental(true).max_depth(4).await
+}
+
+#[actix_rt::test]
+async fn test_incremental_valid() {
+    // headers and strings split across chunks
+    let body = Box::leak(get_test_bytes().into_boxed_slice());
+    let chunks = body.chunks(1).collect::<Vec<_>>();
+    let value = validate_chunks(chunks).await.unwrap();
+    assert_eq!(value, serde_cbor::value::to_value(MyObject::default()).unwrap());
+
+    // indefinite length strings, arrays and maps, tags and simple values (tags are dropped when
+    // decoding to a `Value`)
+    let value = validate_chunks(vec![
+        b"\xbf\x61a\x9f\x01\x19\x01",
+        b"\x00\xff\x61b\x7f\x62xy\x61",
+        b"z\xff\x61c\xc1\x1a\x00\x00\x00\x01\x61d\xf6\xff",
+    ])
+        .await
+        .unwrap();
+    assert_eq!(diagnostic(&value), r#"{"a": [1, 256], "b": "xyz", "c": 1, "d": null}"#);
+}
+
+#[actix_rt::test]
+async fn test_incremental_malformed() {
+    type Case = (Vec<&'static [u8]>, fn(&MalformedError) -> bool);
+
+    let cases: Vec<Case> = vec![
+        // reserved additional information
+        (vec![b"\x82\x01\x1c"], |e| matches!(e, MalformedError::InvalidHeader(2))),
+        // break outside of an indefinite length item
+        (vec![b"\x82\x01", b"\xff"], |e| matches!(e, MalformedError::UnexpectedBreak(2))),
+        // indefinite map with a key but no value
+        (vec![b"\xbf\x01\xff"], |e| matches!(e, MalformedError::UnexpectedBreak(2))),
+        // integer chunk in an indefinite length byte string
+        (vec![b"\x5f\x41a\x01"], |e| matches!(e, MalformedError::InvalidHeader(3))),
+        // nested deeper than the max depth
+        (vec![b"\x81\x81\x81\x81\x81\x01"], |e| matches!(e, MalformedError::TooDeep(4))),
+        // byte string longer than the limit
+        (vec![b"\x81\x5a\x10\x00\x00\x00"], |e| matches!(e, MalformedError::TooLong(1))),
+        // array with more items than can fit
+        (vec![b"\x9b\xff\xff\xff\xff\xff\xff\xff\xff"], |e| matches!(e, MalformedError::TooLong(0))),
+        // more than one top level item
+        (vec![b"\x01\x02"], |e| matches!(e, MalformedError::TrailingData(1))),
+    ];
+
+    for (chunks, expected) in cases {
+        let req = TestRequest::default()
+            .header(header::CONTENT_TYPE, "application/cbor")
+            .to_http_request();
+        let mut pl = endless_payload(chunks);
+
+        // the payload never ends, so this only resolves if it is rejected early
+        let res = CborBody::<serde_cbor::Value>::new(&req, &mut pl, None)
+            .incremental(true)
+            .max_depth(4)
+            .await;
+        match res {
+            Err(CborPayloadError::Malformed(ref e)) if expected(e) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_incremental_config() {
+    let (req, _) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .app_data(CborConfig::default().incremental(true))
+        .to_http_parts();
+    let mut pl = endless_payload(vec![b"\xa1\x64name", b"\xfe"]);
+
+    let res = Cbor::<MyObject>::from_request(&req, &mut pl).await;
+    assert_eq!(Response::from_error(res.err().unwrap()).status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn test_buffer_pool() {
+    let pool = CborBufferPool::new(1);
+
+    for _ in 0..2 {
+        let (req, mut pl) = TestRequest::default()
+            .set_cbor(&MyObject::default())
+            .app_data(pool.clone())
+            .to_http_parts();
+        let s = Cbor::<MyObject>::from_request(&req, &mut pl).await.unwrap();
+        assert_eq!(s.into_inner(), MyObject::default());
+        assert_eq!(pool.idle(), 1);
+    }
+
+    // buffers over the max capacity are dropped
+    let pool = CborBufferPool::new(1).max_capacity(16);
+    let upload = Upload { data: vec![0; 1024] };
+    let (req, mut pl) = TestRequest::default()
+        .set_cbor(&upload)
+        .data(pool.clone())
+        .to_http_parts();
+    assert!(Cbor::<Upload>::from_request(&req, &mut pl).await.is_ok());
+    assert_eq!(pool.idle(), 0);
+}
+
+#[test]
+fn test_initial_capacity() {
+    assert_eq!(crate::body::initial_capacity(Some(100), 1024), 100);
+    assert_eq!(crate::body::initial_capacity(Some(1 << 30), 1024), 1024);
+    assert_eq!(crate::body::initial_capacity(None, 1024), 1024);
+    assert_eq!(crate::body::initial_capacity(None, DEFAULT_LIMIT), 8192);
+}
+
+/// Respond with `upload` to a request with the given `Accept-Encoding`, returning the
+/// `Content-Encoding` and the decoded body.
+#[cfg(feature = "compress")]
+async fn compressed_response(
+    upload: &Upload,
+    config: CborConfig,
+    accept: Option<&str>,
+) -> (Option<String>, Upload) {
+    use std::io::Read;
+
+    let mut req = TestRequest::default().app_data(config);
+    if let Some(accept) = accept {
+        req = req.header(header::ACCEPT_ENCODING, accept);
+    }
+    let req = req.to_http_request();
+
+    let mut resp = Cbor(upload).respond_to(&req).await.unwrap();
+    assert_eq!(resp.headers().get(header::VARY).unwrap(), "accept-encoding");
+    let encoding = resp
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|enc| enc.to_str().unwrap().to_owned());
+    let body = actix_web::test::load_stream(resp.take_body()).await.unwrap();
+
+    let mut decoded = Vec::new();
+    match encoding.as_deref() {
+        None => decoded.extend_from_slice(&body),
+        Some("gzip") => {
+            flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut decoded).unwrap();
+        }
+        Some("br") => {
+            brotli::Decompressor::new(&body[..], 4096).read_to_end(&mut decoded).unwrap();
+        }
+        Some("zstd") => decoded = zstd::stream::decode_all(&body[..]).unwrap(),
+        Some(other) => panic!("unexpected encoding {}", other),
+    }
+
+    (encoding, serde_cbor::from_slice(&decoded).unwrap())
+}
+
+#[cfg(feature = "compress")]
+#[actix_rt::test]
+async fn test_response_compression() {
+    let upload = Upload { data: vec![7; 4096] };
+    let config = CborConfig::default().compress_responses(1024);
+
+    for encoding in &["gzip", "br", "zstd"] {
+        let (enc, body) = compressed_response(&upload, config.clone(), Some(encoding)).await;
+        assert_eq!(enc.as_deref(), Some(*encoding));
+        assert_eq!(body.data, upload.data);
+    }
+
+    // highest quality wins, ties go to zstd, then br
+    let cases = vec![
+        ("gzip, br, zstd", Some("zstd")),
+        ("gzip;q=1.0, br;q=0.8, zstd;q=0.5", Some("gzip")),
+        ("deflate, br", Some("br")),
+        ("*", Some("zstd")),
+        ("*;q=0.5, gzip", Some("gzip")),
+        ("zstd;q=0, br;q=0, *", Some("gzip")),
+        ("identity", None),
+        ("gzip;q=0", None),
+        ("gzip;q=lots", None),
+    ];
+    for (accept, expected) in cases {
+        let (enc, body) = compressed_response(&upload, config.clone(), Some(accept)).await;
+        assert_eq!(enc.as_deref(), expected, "Accept-Encoding: {}", accept);
+        assert_eq!(body.data, upload.data);
+    }
+
+    let (enc, _) = compressed_response(&upload, config.clone(), None).await;
+    assert_eq!(enc, None);
+
+    // below the min size
+    let small = Upload { data: vec![7; 16] };
+    let (enc, body) = compressed_response(&small, config, Some("gzip")).await;
+    assert_eq!(enc, None);
+    assert_eq!(body.data, small.data);
+
+    // disabled by default
+    let req = TestRequest::default()
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .to_http_request();
+    let resp = Cbor(&upload).respond_to(&req).await.unwrap();
+    assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    assert!(resp.headers().get(header::VARY).is_none());
+}
+
+#[cfg(feature = "compress")]
+fn zstd_request(body: &[u8], dictionary: Option<(&str, &[u8])>) -> TestRequest {
+    let (compressed, req) = match dictionary {
+        Some((id, dictionary)) => (
+            zstd::bulk::Compressor::with_dictionary(3, dictionary).unwrap().compress(body).unwrap(),
+            TestRequest::default().header("zstd-dictionary-id", id),
+        ),
+        None => (zstd::bulk::compress(body, 3).unwrap(), TestRequest::default()),
+    };
+
+    req.header(header::CONTENT_TYPE, "application/cbor")
+        .header(header::CONTENT_ENCODING, "zstd")
+        .header(header::CONTENT_LENGTH, compressed.len())
+        .set_payload(compressed)
+}
+
+#[cfg(feature = "compress")]
+#[actix_rt::test]
+async fn test_zstd_dictionaries() {
+    // any content can be used as a raw dictionary
+    let dictionary = serde_cbor::to_vec(&vec![MyObject::default(); 4]).unwrap();
+    let dictionaries = ZstdDictionaries::new().dictionary("objects", &dictionary);
+    let body = get_test_bytes();
+
+    let (req, mut pl) = zstd_request(&body, Some(("objects", &dictionary)))
+        .app_data(dictionaries.clone())
+        .to_http_parts();
+    let s = Cbor::<MyObject>::from_request(&req, &mut pl).await.unwrap();
+    assert_eq!(s.into_inner(), MyObject::default());
+
+    // zstd without a dictionary
+    let (req, mut pl) = zstd_request(&body, None)
+        .app_data(dictionaries.clone())
+        .to_http_parts();
+    let s = CborBody::<MyObject>::new(&req, &mut pl, None).await;
+    assert_eq!(s.unwrap(), MyObject::default());
+
+    // unknown dictionary
+    let (req, mut pl) = zstd_request(&body, Some(("other", &dictionary)))
+        .app_data(dictionaries.clone())
+        .to_http_parts();
+    let s = CborBody::<MyObject>::new(&req, &mut pl, None).await;
+    assert!(matches!(s.err().unwrap(), CborPayloadError::UnknownDictionary));
+
+    // the encoding is matched like the other encodings, ignoring case and padding
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .header(header::CONTENT_ENCODING, " ZSTD ")
+        .header("zstd-dictionary-id", "other")
+        .app_data(dictionaries.clone())
+        .set_payload(zstd::bulk::compress(&body, 3).unwrap())
+        .to_http_parts();
+    let s = CborBody::<MyObject>::new(&req, &mut pl, None).await;
+    assert!(matches!(s.err().unwrap(), CborPayloadError::UnknownDictionary));
+
+    // dictionaries not registered
+    let (req, mut pl) = zstd_request(&body, Some(("objects", &dictionary))).to_http_parts();
+    let s = CborBody::<MyObject>::new(&req, &mut pl, None).await;
+    assert!(matches!(s.err().unwrap(), CborPayloadError::Payload(_)));
+
+    // the limit applies to the decompressed payload
+    let upload = serde_cbor::to_vec(&Upload { data: vec![0; 1 << 20] }).unwrap();
+    let (req, mut pl) = zstd_request(&upload, None).to_http_parts();
+    let s = CborBody::<Upload>::new(&req, &mut pl, None).await;
+    assert!(matches!(s.err().unwrap(), CborPayloadError::Overflow));
+}
+
+#[cfg(feature = "compress")]
+#[actix_rt::test]
+async fn test_zstd_dictionary_response() {
+    let dictionary = serde_cbor::to_vec(&vec![MyObject::default(); 4]).unwrap();
+    let dictionaries = ZstdDictionaries::new().dictionary("objects", &dictionary);
+    let data = vec![MyObject::default(); 16];
+
+    let req = TestRequest::default()
+        .header(header::ACCEPT_ENCODING, "gzip, zstd")
+        .header("zstd-dictionary-id", "objects")
+        .app_data(dictionaries.clone())
+        .app_data(CborConfig::default().compress_responses(0))
+        .to_http_request();
+    let mut resp = Cbor(&data).respond_to(&req).await.unwrap();
+    assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "zstd");
+    assert_eq!(resp.headers().get("zstd-dictionary-id").unwrap(), "objects");
+    assert_eq!(
+        resp.headers().get(header::VARY).unwrap(),
+        "accept-encoding, zstd-dictionary-id"
+    );
+
+    let body = actix_web::test::load_stream(resp.take_body()).await.unwrap();
+    let decoded = zstd::bulk::Decompressor::with_dictionary(&dictionary)
+        .unwrap()
+        .decompress(&body, 1 << 16)
+        .unwrap();
+    assert_eq!(serde_cbor::from_slice::<Vec<MyObject>>(&decoded).unwrap(), data);
+
+    // other encodings don't use the dictionary
+    let req = TestRequest::default()
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .header("zstd-dictionary-id", "objects")
+        .app_data(dictionaries)
+        .app_data(CborConfig::default().compress_responses(0))
+        .to_http_request();
+    let resp = Cbor(&data).respond_to(&req).await.unwrap();
+    assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    assert!(resp.headers().get("zstd-dictionary-id").is_none());
+}
+
+#[actix_rt::test]
+async fn test_etag() {
+    use std::collections::HashMap;
+
+    let config = CborConfig::default().etag(true);
+    let req = TestRequest::default().app_data(config.clone()).to_http_request();
+
+    // equal maps get the same tag regardless of their iteration order
+    let a: HashMap<String, u32> = (0..32).map(|i| (i.to_string(), i)).collect();
+    let b: HashMap<String, u32> = (0..32).rev().map(|i| (i.to_string(), i)).collect();
+    let resp_a = Cbor(&a).respond_to(&req).await.unwrap();
+    let resp_b = Cbor(&b).respond_to(&req).await.unwrap();
+    let tag = resp_a.headers().get(header::ETAG).unwrap().clone();
+    assert!(tag.to_str().unwrap().starts_with('"'));
+    assert_eq!(resp_b.headers().get(header::ETAG).unwrap(), &tag);
+    assert_eq!(read_cbor_response::<HashMap<String, u32>>(resp_a).await, a);
+
+    let resp = Cbor(MyObject::default()).respond_to(&req).await.unwrap();
+    assert_ne!(resp.headers().get(header::ETAG).unwrap(), &tag);
+
+    // matching If-None-Match
+    let etag = tag.to_str().unwrap();
+    for if_none_match in &[etag.to_owned(), format!("\"other\", W/{}", etag), "*".to_owned()] {
+        let req = TestRequest::default()
+            .header(header::IF_NONE_MATCH, if_none_match.as_str())
+            .app_data(config.clone())
+            .to_http_request();
+        let mut resp = Cbor(&a).respond_to(&req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &tag);
+        assert!(actix_web::test::load_stream(resp.take_body()).await.unwrap().is_empty());
+    }
+
+    // not matching, or not a GET or HEAD request
+    let req = TestRequest::default()
+        .header(header::IF_NONE_MATCH, "\"other\"")
+        .app_data(config.clone())
+        .to_http_request();
+    assert_eq!(Cbor(&a).respond_to(&req).await.unwrap().status(), StatusCode::OK);
+
+    let req = TestRequest::post()
+        .header(header::IF_NONE_MATCH, etag)
+        .app_data(config)
+        .to_http_request();
+    assert_eq!(Cbor(&a).respond_to(&req).await.unwrap().status(), StatusCode::OK);
+
+    // disabled by default
+    let req = TestRequest::default().header(header::IF_NONE_MATCH, "*").to_http_request();
+    let resp = Cbor(&a).respond_to(&req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(header::ETAG).is_none());
+}
+
+#[cfg(feature = "compress")]
+#[actix_rt::test]
+async fn test_etag_compressed() {
+    let config = CborConfig::default().etag(true).compress_responses(0);
+    let data = vec![MyObject::default(); 16];
+
+    let tag = |accept: &'static str| {
+        let req = TestRequest::default()
+            .header(header::ACCEPT_ENCODING, accept)
+            .app_data(config.clone())
+            .to_http_request();
+        let resp = Cbor(&data).respond_to(&req).into_inner().unwrap();
+        resp.headers().get(header::ETAG).unwrap().clone()
+    };
+
+    // each encoding is a different representation
+    assert_ne!(tag("gzip"), tag("identity"));
+    assert_ne!(tag("gzip"), tag("br"));
+    assert_eq!(tag("gzip"), tag("gzip"));
+
+    // a 304 is sent without compressing the body
+    let req = TestRequest::default()
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .header(header::IF_NONE_MATCH, tag("gzip"))
+        .app_data(config.clone())
+        .to_http_request();
+    let resp = Cbor(&data).respond_to(&req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(resp.headers().get(header::VARY).unwrap(), "accept-encoding");
+}
+
+#[actix_rt::test]
+async fn test_ranges() {
+    let config = CborConfig::default().ranges(true).etag(true);
+    let data = Upload { data: (0..=255).collect() };
+    let body = serde_cbor::to_vec(&data).unwrap();
+    let len = body.len();
+
+    let respond = |req: TestRequest| {
+        let req = req.app_data(config.clone()).to_http_request();
+        Cbor(&data).respond_to(&req).into_inner().unwrap()
+    };
+    let read = |mut resp: Response| async move {
+        actix_web::test::load_stream(resp.take_body()).await.unwrap()
+    };
+
+    let resp = respond(TestRequest::default());
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+    let tag = resp.headers().get(header::ETAG).unwrap().clone();
+
+    let cases = vec![
+        ("bytes=0-9", 0..10),
+        ("bytes=10-", 10..len),
+        ("bytes=-5", len - 5..len),
+        ("bytes=-100000", 0..len),
+        ("bytes=100-100000", 100..len),
+    ];
+    for (range, expected) in cases {
+        let resp = respond(TestRequest::default().header(header::RANGE, range));
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap().to_str().unwrap(),
+            format!("bytes {}-{}/{}", expected.start, expected.end - 1, len)
+        );
+        assert_eq!(&read(resp).await[..], &body[expected]);
+    }
+
+    // resuming a download with If-Range
+    let resp = respond(
+        TestRequest::default()
+            .header(header::RANGE, "bytes=100-")
+            .header(header::IF_RANGE, tag.clone()),
+    );
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(&read(resp).await[..], &body[100..]);
+
+    let resp = respond(
+        TestRequest::default()
+            .header(header::RANGE, "bytes=100-")
+            .header(header::IF_RANGE, "\"changed\""),
+    );
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(read(resp).await, body);
+
+    // unsatisfiable
+    for range in &["bytes=100000-", "bytes=-0"] {
+        let resp = respond(TestRequest::default().header(header::RANGE, *range));
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap().to_str().unwrap(),
+            format!("bytes */{}", len)
+        );
+    }
+
+    // ignored: malformed, several ranges, other units and other methods
+    for range in &["bytes=5-1", "bytes=0-1, 4-5", "items=0-1", "bytes=a-b"] {
+        let resp = respond(TestRequest::default().header(header::RANGE, *range));
+        assert_eq!(resp.status(), StatusCode::OK, "{}", range);
+    }
+    let resp = respond(TestRequest::post().header(header::RANGE, "bytes=0-9"));
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // HEAD gets the same headers as GET, the body is dropped by the server
+    let resp = respond(
+        TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .header(header::RANGE, "bytes=0-9"),
+    );
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(read(resp).await.len(), 10);
+
+    // disabled by default
+    let req = TestRequest::default().header(header::RANGE, "bytes=0-9").to_http_request();
+    let resp = Cbor(&data).respond_to(&req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get(header::ACCEPT_RANGES).is_none());
+}
+
+#[actix_rt::test]
+async fn test_head_content_length() {
+    let srv = actix_web::test::start(|| {
+        actix_web::App::new()
+            .app_data(CborConfig::default().ranges(true))
+            .route("/", web::route().to(|| async { Cbor(Upload { data: vec![1; 1000] }) }))
+    });
+    let len = serde_cbor::to_vec(&Upload { data: vec![1; 1000] }).unwrap().len();
+
+    let mut res = srv.head("/").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap().to_str().unwrap(), len.to_string());
+    assert!(res.body().await.unwrap().is_empty());
+
+    let mut res = srv.head("/").header(header::RANGE, "bytes=-10").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "10");
+    assert!(res.body().await.unwrap().is_empty());
+}
+
+fn base64_param<T: Serialize>(value: &T) -> String {
+    base64::encode_config(serde_cbor::to_vec(value).unwrap(), base64::URL_SAFE_NO_PAD)
+}
+
+#[actix_rt::test]
+async fn test_cbor_query() {
+    let param = base64_param(&MyObject::default());
+
+    let (req, mut pl) = TestRequest::with_uri(&format!("/?other=1&cbor={}", param)).to_http_parts();
+    let s = CborQuery::<MyObject>::from_request(&req, &mut pl).await.unwrap();
+    assert_eq!(s.into_inner(), MyObject::default());
+
+    // padded and percent-encoded
+    let padded = base64::encode_config(get_test_bytes(), base64::URL_SAFE).replace('=', "%3D");
+    let (req, mut pl) = TestRequest::with_uri(&format!("/?filter={}", padded))
+        .app_data(CborConfig::default().query_param("filter"))
+        .to_http_parts();
+    let s = CborQuery::<MyObject>::from_request(&req, &mut pl).await.unwrap();
+    assert_eq!(s.into_inner(), MyObject::default());
+
+    let status = |req: HttpRequest| async move {
+        let e = CborQuery::<MyObject>::from_request(&req, &mut Payload::None).await.err().unwrap();
+        Response::from_error(e).status()
+    };
+
+    assert_eq!(status(TestRequest::with_uri("/?other=1").to_http_request()).await, StatusCode::BAD_REQUEST);
+    assert_eq!(status(TestRequest::with_uri("/?cbor=a+b").to_http_request()).await, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        status(TestRequest::with_uri(&format!("/?cbor={}", base64_param(&1))).to_http_request()).await,
+        StatusCode::BAD_REQUEST
+    );
+    let req = TestRequest::with_uri(&format!("/?cbor={}", param))
+        .app_data(CborConfig::default().limit(get_test_bytes().len() - 1))
+        .to_http_request();
+    assert_eq!(status(req).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // the limit is checked against the decoded size
+    let req = TestRequest::with_uri(&format!("/?cbor={}", param))
+        .app_data(CborConfig::default().limit(get_test_bytes().len()))
+        .to_http_request();
+    assert!(CborQuery::<MyObject>::from_request(&req, &mut Payload::None).await.is_ok());
+
+    // custom error handler
+    let req = TestRequest::with_uri("/")
+        .app_data(CborConfig::default().error_handler(|err, _| {
+            assert!(matches!(err, CborPayloadError::MissingParameter));
+            InternalError::from_response(err, HttpResponse::Conflict().finish()).into()
+        }))
+        .to_http_request();
+    assert_eq!(status(req).await, StatusCode::CONFLICT);
+}
+
+#[actix_rt::test]
+async fn test_cbor_path() {
+    let mut app = actix_web::test::init_service(
+        actix_web::App::new()
+            .route("/first/{obj}", web::get().to(|obj: CborPath<MyObject>| async move {
+                Cbor(obj.into_inner())
+            }))
+            .service(
+                web::resource("/named/{id}/{obj}")
+                    .app_data(CborConfig::default().path_param("obj"))
+                    .route(web::get().to(|obj: CborPath<MyObject>| async move {
+                        Cbor(obj.into_inner())
+                    })),
+            ),
+    )
+        .await;
+
+    let param = base64_param(&MyObject::default());
+    for uri in &[format!("/first/{}", param), format!("/named/7/{}", param)] {
+        let req = TestRequest::with_uri(uri).to_request();
+        let res = actix_web::test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(read_cbor_body::<MyObject, _>(res).await, MyObject::default());
+    }
+
+    let req = TestRequest::with_uri("/first/not-cbor").to_request();
+    let res = actix_web::test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+struct TestHeader;
+
+impl CborHeaderName for TestHeader {
+    const NAME: &'static str = "x-cbor-context";
+}
+
+#[actix_rt::test]
+async fn test_cbor_header() {
+    let (req, mut pl) = TestRequest::default()
+        .header("x-cbor-context", base64_param(&MyObject::default()))
+        .to_http_parts();
+    let s = CborHeader::<MyObject, TestHeader>::from_request(&req, &mut pl).await.unwrap();
+    assert_eq!(s.into_inner(), MyObject::default());
+
+    let status = |req: HttpRequest| async move {
+        let e = CborHeader::<MyObject, TestHeader>::from_request(&req, &mut Payload::None)
+            .await
+            .err()
+            .unwrap();
+        Response::from_error(e).status()
+    };
+
+    assert_eq!(status(TestRequest::default().to_http_request()).await, StatusCode::BAD_REQUEST);
+    let req = TestRequest::default()
+        .header("x-cbor-context", header::HeaderValue::from_bytes(b"\xe9t\xe9").unwrap())
+        .to_http_request();
+    assert_eq!(status(req).await, StatusCode::BAD_REQUEST);
+    let req = TestRequest::default()
+        .header("x-cbor-context", base64_param(&MyObject::default()))
+        .app_data(CborConfig::default().limit(get_test_bytes().len() - 1))
+        .to_http_request();
+    assert_eq!(status(req).await, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[cfg(feature = "ws")]
+fn ws_frames(msgs: Vec<actix_http::ws::Message>) -> web::BytesMut {
+    use actix_codec::Encoder;
+
+    let mut client = actix_http::ws::Codec::new().client_mode();
+    let mut buf = web::BytesMut::new();
+    for msg in msgs {
+        client.encode(msg, &mut buf).unwrap();
+    }
+    buf
+}
+
+#[cfg(feature = "ws")]
+#[test]
+fn test_ws_codec() {
+    use actix_codec::{Decoder, Encoder};
+    use actix_http::ws::{CloseCode, Item, Message};
+
+    let bytes = web::Bytes::from(get_test_bytes());
+    let mut codec = CborWsCodec::<MyObject, MyObject>::new();
+
+    // whole and fragmented messages, with control messages in between
+    let mut buf = ws_frames(vec![
+        Message::Binary(bytes.clone()),
+        Message::Continuation(Item::FirstBinary(bytes.slice(..3))),
+        Message::Ping(web::Bytes::from_static(b"ping")),
+        Message::Continuation(Item::Continue(bytes.slice(3..5))),
+        Message::Continuation(Item::Last(bytes.slice(5..))),
+    ]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(CborWsMessage::Cbor(MyObject::default())));
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(CborWsMessage::Ping(web::Bytes::from_static(b"ping")))
+    );
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(CborWsMessage::Cbor(MyObject::default())));
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+    // encoded messages decode on the client
+    let mut buf = web::BytesMut::new();
+    codec.encode(CborWsMessage::Cbor(MyObject::default()), &mut buf).unwrap();
+    let mut client = CborWsCodec::<MyObject, MyObject>::new().client_mode();
+    assert_eq!(client.decode(&mut buf).unwrap(), Some(CborWsMessage::Cbor(MyObject::default())));
+
+    let code = |codec: &mut CborWsCodec<MyObject, MyObject>, msgs| {
+        codec.decode(&mut ws_frames(msgs)).unwrap_err().close_reason().code
+    };
+
+    let mut codec = CborWsCodec::<MyObject, MyObject>::new().limit(bytes.len() - 1);
+    assert_eq!(code(&mut codec, vec![Message::Binary(bytes.clone())]), CloseCode::Size);
+    assert_eq!(
+        code(&mut codec, vec![
+            Message::Continuation(Item::FirstBinary(bytes.slice(..5))),
+            Message::Continuation(Item::Last(bytes.slice(5..))),
+        ]),
+        CloseCode::Size
+    );
+    assert_eq!(code(&mut codec, vec![Message::Text("{}".to_owned())]), CloseCode::Unsupported);
+    assert_eq!(
+        code(&mut codec, vec![Message::Binary(web::Bytes::from_static(&[0x01]))]),
+        CloseCode::Invalid
+    );
+
+    // the rest of a rejected text message is skipped
+    let mut codec = CborWsCodec::<MyObject, MyObject>::new();
+    let mut buf = ws_frames(vec![
+        Message::Continuation(Item::FirstText(web::Bytes::from_static(b"{"))),
+        Message::Continuation(Item::Continue(web::Bytes::from_static(b"\"a\""))),
+        Message::Continuation(Item::Last(web::Bytes::from_static(b": 1}"))),
+        Message::Binary(bytes.clone()),
+    ]);
+    let err = codec.decode(&mut buf).unwrap_err();
+    assert_eq!(err.close_reason().code, CloseCode::Unsupported);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(CborWsMessage::Cbor(MyObject::default())));
+
+    // unmasked frames from the server are rejected
+    let mut buf = web::BytesMut::new();
+    actix_http::ws::Codec::new().encode(Message::Binary(bytes.clone()), &mut buf).unwrap();
+    assert_eq!(codec.decode(&mut buf).unwrap_err().close_reason().code, CloseCode::Protocol);
+}
+
+#[cfg(feature = "ws")]
+#[test]
+fn test_ws_messages() {
+    use actix_http::ws::{CloseCode, Item, Message};
+
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(4))
+        .to_http_request();
+    let mut codec = CborWsCodec::<MyObject, MyObject>::from_req(&req);
+
+    let msg = codec.decode_message(Message::Binary(get_test_bytes().into())).unwrap();
+    assert_eq!(msg, Some(CborWsMessage::Cbor(MyObject::default())));
+    assert_eq!(codec.decode_message(Message::Nop).unwrap(), None);
+
+    let err = codec.encode_message(&MyObject::default()).unwrap_err();
+    assert_eq!(err.close_reason().code, CloseCode::Error);
+
+    let codec = CborWsCodec::<MyObject, i32>::new();
+    assert_eq!(codec.encode_message(&1).unwrap(), Message::Binary(vec![0x01].into()));
+
+    // continuations without a message and new messages before the last one ended
+    let bytes = web::Bytes::from(get_test_bytes());
+    let first = || Message::Continuation(Item::FirstBinary(bytes.slice(..3)));
+    for msgs in [
+        vec![Message::Continuation(Item::Continue(bytes.slice(..3)))],
+        vec![Message::Continuation(Item::Last(bytes.clone()))],
+        vec![first(), first()],
+        vec![first(), Message::Continuation(Item::FirstText(bytes.slice(..3)))],
+        vec![first(), Message::Binary(bytes.clone())],
+        vec![first(), Message::Text("{}".to_owned())],
+    ] {
+        let mut codec = CborWsCodec::<MyObject, MyObject>::new();
+        let err = msgs.into_iter().map(|msg| codec.decode_message(msg)).find_map(Result::err);
+        assert_eq!(err.unwrap().close_reason().code, CloseCode::Protocol);
+    }
+
+    // a new message can start after the error
+    let mut codec = CborWsCodec::<MyObject, MyObject>::new();
+    assert!(codec.decode_message(first()).unwrap().is_none());
+    assert!(codec.decode_message(first()).is_err());
+    assert!(codec.decode_message(first()).unwrap().is_none());
+    let msg = codec.decode_message(Message::Continuation(Item::Last(bytes.slice(3..))));
+    assert_eq!(msg.unwrap(), Some(CborWsMessage::Cbor(MyObject::default())));
+}
+
+async fn collect_events<T: serde::de::DeserializeOwned>(
+    body: web::Bytes,
+    framing: CborFraming,
+) -> Vec<Result<T, CborPayloadError>> {
+    use futures_util::stream::{self, StreamExt};
+
+    // one byte at a time, so that events are split across chunks
+    let chunks = (0..body.len()).map(|i| Ok::<_, actix_http::error::PayloadError>(body.slice(i..i + 1)));
+    CborEventStream::new(stream::iter(chunks.collect::<Vec<_>>()), framing)
+        .collect()
+        .await
+}
+
+#[actix_rt::test]
+async fn test_events() {
+    use futures_util::stream;
+
+    for &framing in &[CborFraming::Sequence, CborFraming::LengthPrefixed] {
+        let mut app = actix_web::test::init_service(actix_web::App::new().route(
+            "/",
+            web::get().to(move || async move {
+                CborEvents::new(stream::iter(vec![MyObject::default(); 3])).framing(framing)
+            }),
+        ))
+            .await;
+
+        let res = actix_web::test::call_service(&mut app, TestRequest::default().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let content_type = res.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap();
+        assert_eq!(CborFraming::from_content_type(content_type), Some(framing));
+        assert_eq!(res.headers().get(header::CACHE_CONTROL).unwrap(), "no-cache");
+
+        let body = actix_web::test::read_body(res).await;
+        let events = collect_events::<MyObject>(body, framing).await;
+        assert_eq!(events.len(), 3);
+        assert!(events.into_iter().all(|event| event.unwrap() == MyObject::default()));
+    }
+}
+
+#[actix_rt::test]
+async fn test_events_heartbeat() {
+    use std::time::Duration;
+
+    use futures_util::sink::SinkExt;
+    use serde_cbor::Value;
+
+    for &(framing, heartbeat) in &[
+        (CborFraming::Sequence, &[0xf7][..]),
+        (CborFraming::LengthPrefixed, &[0, 0, 0, 0][..]),
+    ] {
+        let mut app = actix_web::test::init_service(actix_web::App::new().route(
+            "/",
+            web::get().to(move || async move {
+                let (mut tx, events) = CborEvents::channel(1);
+                actix_rt::spawn(async move {
+                    tx.send(1).await.unwrap();
+                    actix_rt::time::delay_for(Duration::from_millis(50)).await;
+                    tx.send(2).await.unwrap();
+                });
+
+                events.framing(framing).heartbeat(Duration::from_millis(10))
+            }),
+        ))
+            .await;
+
+        let res = actix_web::test::call_service(&mut app, TestRequest::default().to_request()).await;
+        let body = actix_web::test::read_body(res).await;
+        assert!(body.windows(heartbeat.len()).any(|window| window == heartbeat));
+
+        let events = collect_events::<u32>(body, framing).await;
+        let events = events.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(events, vec![1, 2]);
+    }
+
+    // null events are kept apart from heartbeats, which also skip top-level `undefined` items
+    let body = web::Bytes::from_static(&[0xf6, 0xf7, 0x81, 0x01, 0xf7, 0xf7, 0x82, 0xf7, 0xf6]);
+    let events = collect_events::<serde_cbor::Value>(body, CborFraming::Sequence).await;
+    let events = events.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![Value::Null, Value::Array(vec![Value::Integer(1)]), Value::Array(vec![Value::Null, Value::Null])]
+    );
+}
+
+#[actix_rt::test]
+async fn test_events_errors() {
+    use futures_util::stream::{self, StreamExt};
+
+    // events over the response limit abort the response
+    let req = TestRequest::default()
+        .app_data(CborConfig::default().response_limit(4))
+        .to_http_request();
+    let mut res = CborEvents::new(stream::iter(vec![1, 1_000_000, 2]))
+        .respond_to(&req)
+        .await
+        .unwrap();
+    let chunks = res.take_body().collect::<Vec<_>>().await;
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].as_ref().unwrap(), &[0x01][..]);
+    assert!(chunks[1].is_err());
+
+    let body = web::Bytes::from_static(&[0, 0, 0, 9, 0x01]);
+    let events = collect_events::<u32>(body.clone(), CborFraming::LengthPrefixed).await;
+    assert!(matches!(events[..], [Err(CborPayloadError::Payload(_))]));
+
+    let events: Vec<Result<u32, _>> = CborEventStream::new(
+        stream::iter(vec![Ok::<_, actix_http::error::PayloadError>(body)]),
+        CborFraming::LengthPrefixed,
+    )
+        .limit(8)
+        .collect()
+        .await;
+    assert!(matches!(events[..], [Err(CborPayloadError::Overflow)]));
+
+    let body = web::Bytes::from_static(&[0x01, 0x61, 0x61]);
+    let events = collect_events::<u32>(body, CborFraming::Sequence).await;
+    assert!(matches!(events[..], [Ok(1), Err(CborPayloadError::Deserialize(_))]));
+
+    // malformed items fail as soon as they arrive, with offsets within the item
+    let body = web::Bytes::from_static(&[0x01, 0x82, 0x1c, 0x01]);
+    let events = collect_events::<u32>(body, CborFraming::Sequence).await;
+    assert!(matches!(
+        events[..],
+        [Ok(1), Err(CborPayloadError::Malformed(MalformedError::InvalidHeader(1)))]
+    ));
+
+    // as do items declaring a length over the limit, before it is buffered
+    let body = web::Bytes::from_static(&[0x01, 0x5a, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    let events: Vec<Result<u32, _>> = CborEventStream::new(
+        stream::iter(vec![Ok::<_, actix_http::error::PayloadError>(body)]),
+        CborFraming::Sequence,
+    )
+        .limit(8)
+        .collect()
+        .await;
+    assert!(matches!(events[..], [Ok(1), Err(CborPayloadError::Overflow)]));
+}
+
+#[cfg(feature = "client")]
+#[actix_rt::test]
+async fn test_client_cbor_events() {
+    use awc::test::TestResponse;
+    use futures_util::stream::StreamExt;
+
+    let mut res = TestResponse::default().finish();
+    assert!(cbor_eq(res.cbor_events::<u32>().err().unwrap(), CborPayloadError::ContentType));
+
+    let mut res = TestResponse::default()
+        .header(header::CONTENT_TYPE, "application/x-cbor-frames")
+        .set_payload(vec![0, 0, 0, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 1, 0x02])
+        .finish();
+    let events = res.cbor_events::<u32>().unwrap().collect::<Vec<_>>().await;
+    let events = events.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(events, vec![1, 2]);
+}
+
+#[actix_rt::test]
+async fn test_coap_content_format() {
+    let formats = CoapContentFormats::new().format(65000, "application/vnd.example+cbor");
+    assert_eq!(formats.media_type(60), Some("application/cbor"));
+    assert_eq!(formats.content_format("application/cbor-seq"), Some(63));
+    assert_eq!(formats.media_type(50), None);
+
+    let extract = |format: &'static str, body: Vec<u8>, incremental: bool| {
+        let formats = formats.clone();
+        async move {
+            let (req, mut pl) = TestRequest::default()
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header("coap-content-format", format)
+                .app_data(formats)
+                .app_data(CborConfig::default().incremental(incremental))
+                .set_payload(body)
+                .to_http_parts();
+            Cbor::<Vec<MyObject>>::from_request(&req, &mut pl).await
+        }
+    };
+
+    let objects = vec![MyObject::default(), MyObject::default()];
+    let array = serde_cbor::to_vec(&objects).unwrap();
+    let sequence = [get_test_bytes(), get_test_bytes()].concat();
+
+    for &incremental in &[false, true] {
+        assert_eq!(extract("60", array.clone(), incremental).await.unwrap().into_inner(), objects);
+        assert_eq!(extract("65000", array.clone(), incremental).await.unwrap().into_inner(), objects);
+        assert_eq!(extract("63", sequence.clone(), incremental).await.unwrap().into_inner(), objects);
+        assert!(extract("63", Vec::new(), incremental).await.unwrap().is_empty());
+
+        // a break can't end the sequence early
+        let mut broken = get_test_bytes();
+        broken.push(0xff);
+        broken.extend(get_test_bytes());
+        let err = extract("63", broken, incremental).await.err().unwrap();
+        assert_eq!(Response::from_error(err).status(), StatusCode::BAD_REQUEST);
+    }
+
+    // COSE messages need to be verified, and COSE keys and other types have to be accepted by
+    // the content type predicate like any content type
+    for &format in &["50", "cbor", "70000", "17", "18", "61", "101", "65001"] {
+        let err = extract(format, array.clone(), false).await.err().unwrap();
+        let err = err.as_error::<CborPayloadError>().unwrap();
+        assert!(matches!(err, CborPayloadError::ContentType));
+    }
+
+    let (req, mut pl) = TestRequest::default()
+        .header("coap-content-format", "65001")
+        .app_data(formats.clone().format(65001, "application/vnd.example"))
+        .app_data(CborConfig::default().content_type_raw(|mime| mime == "application/vnd.example"))
+        .set_payload(array.clone())
+        .to_http_parts();
+    assert!(Cbor::<Vec<MyObject>>::from_request(&req, &mut pl).await.is_ok());
+
+    // without the header, the content type is checked as usual
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cbor")
+        .app_data(formats.clone())
+        .set_payload(array.clone())
+        .to_http_parts();
+    assert!(Cbor::<Vec<MyObject>>::from_request(&req, &mut pl).await.is_ok());
+
+    // and the header is ignored without the mapping
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header("coap-content-format", "60")
+        .set_payload(array)
+        .to_http_parts();
+    assert!(Cbor::<Vec<MyObject>>::from_request(&req, &mut pl).await.is_err());
+}
+
+#[cfg(feature = "openapi")]
+#[test]
+fn test_openapi() {
+    use utoipa::OpenApi;
+
+    #[derive(Deserialize, utoipa::ToSchema)]
+    #[allow(dead_code)]
+    struct User {
+        name: String,
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/users",
+        request_body(content = User, content_type = "application/cbor"),
+        responses(Cbor<u64>, CborPayloadError, CborResponseError)
+    )]
+    #[allow(dead_code)]
+    async fn create_user(user: Cbor<User>) -> Cbor<u64> {
+        Cbor(user.name.len() as u64)
+    }
+
+    #[derive(OpenApi)]
+    #[openapi(paths(create_user), components(schemas(User)))]
+    struct Api;
+
+    let api = Api::openapi();
+    let operation = api.paths.paths["/users"].post.as_ref().unwrap();
+
+    let request = operation.request_body.as_ref().unwrap();
+    assert!(request.content.contains_key("application/cbor"));
+
+    let responses = &operation.responses.responses;
+    #[cfg(not(feature = "cose"))]
+    assert_eq!(responses.keys().collect::<Vec<_>>(), vec!["200", "400", "413", "500"]);
+    #[cfg(feature = "cose")]
+    assert_eq!(responses.keys().collect::<Vec<_>>(), vec!["200", "400", "401", "413", "500"]);
+    match responses["200"] {
+        utoipa::openapi::RefOr::T(ref response) => {
+            assert!(response.content.contains_key("application/cbor"))
+        }
+        _ => panic!("expected an inline response"),
+    }
+
+    let body = cbor_request_body::<User>();
+    assert!(body.content["application/cbor"].schema.is_some());
+}
+
+#[cfg(feature = "cddl")]
+#[test]
+fn test_cddl_structs() {
+    use std::collections::HashMap;
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct Device {
+        device_id: u32,
+        #[serde(rename = "fw")]
+        firmware: String,
+        #[serde(skip)]
+        #[allow(dead_code)]
+        cache: Vec<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        location: Option<Location>,
+        #[serde(flatten)]
+        meta: Meta,
+        #[serde(flatten)]
+        extra: HashMap<String, i64>,
+        tags: Vec<String>,
+        parent: Option<Box<Device>>,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    struct Location(f64, f64);
+
+    #[derive(Serialize, CddlSchema)]
+    struct Meta {
+        #[serde(default)]
+        note: String,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(transparent)]
+    struct Serial(u64);
+
+    #[derive(Serialize, CddlSchema)]
+    struct Unit;
+
+    #[derive(Serialize, CddlSchema)]
+    struct Page<T> {
+        items: Vec<T>,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(tag = "kind", rename = "device-info", rename_all = "camelCase")]
+    struct Tagged {
+        serial_no: u32,
+    }
+
+    let mut generator = CddlGenerator::new();
+    assert_eq!(generator.add::<Device>(), "Device");
+    assert_eq!(generator.add::<Serial>(), "Serial");
+    assert_eq!(generator.add::<Unit>(), "Unit");
+    assert_eq!(generator.add::<Page<Serial>>(), "Page-Serial");
+    assert_eq!(generator.add::<[u8; 4]>(), "[4*4 uint]");
+    assert_eq!(
+        generator.to_string(),
+        "Device = { \"deviceId\": uint, \"fw\": tstr, ? \"location\": Location / null, ~Meta, \
+         * tstr => int, \"tags\": [* tstr], ? \"parent\": Device / null }\n\
+         Location = [float, float]\n\
+         Meta = { ? \"note\": tstr }\n\
+         Serial = uint\n\
+         Unit = null\n\
+         Page-Serial = { \"items\": [* Serial] }\n"
+    );
+
+    // the tag of a struct is the first entry, as serde encodes it
+    let mut generator = CddlGenerator::new();
+    generator.add::<Tagged>();
+    assert_eq!(generator.to_string(), "Tagged = { \"kind\": \"device-info\", \"serialNo\": uint }\n");
+    let value: serde_cbor::Value =
+        serde_cbor::from_slice(&serde_cbor::to_vec(&Tagged { serial_no: 1 }).unwrap()).unwrap();
+    assert_eq!(diagnostic(&value), "{\"kind\": \"device-info\", \"serialNo\": 1}");
+}
+
+#[cfg(feature = "cddl")]
+#[test]
+fn test_cddl_rule_names() {
+    mod a {
+        #[derive(serde::Serialize, crate::CddlSchema)]
+        pub struct Error {
+            pub code: u32,
+        }
+    }
+
+    mod b {
+        #[derive(serde::Serialize, crate::CddlSchema)]
+        pub struct Error {
+            pub message: String,
+        }
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    struct Node<T> {
+        value: T,
+        children: Vec<Node<T>>,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    struct Errors {
+        a: a::Error,
+        b: b::Error,
+    }
+
+    let mut generator = CddlGenerator::new();
+    assert_eq!(generator.add::<Errors>(), "Errors");
+    assert_eq!(generator.add::<a::Error>(), "Error");
+    assert_eq!(generator.add::<b::Error>(), "Error-2");
+    assert_eq!(generator.add::<Node<String>>(), "Node-String");
+    assert_eq!(generator.add::<Node<[u8; 4]>>(), "Node-u8-4");
+    assert_eq!(
+        generator.to_string(),
+        "Errors = { \"a\": Error, \"b\": Error-2 }\n\
+         Error = { \"code\": uint }\n\
+         Error-2 = { \"message\": tstr }\n\
+         Node-String = { \"value\": tstr, \"children\": [* Node-String] }\n\
+         Node-u8-4 = { \"value\": [4*4 uint], \"children\": [* Node-u8-4] }\n"
+    );
+}
+
+#[cfg(feature = "cddl")]
+#[test]
+fn test_cddl_enums() {
+    #[derive(Serialize, CddlSchema)]
+    #[serde(rename_all = "snake_case")]
+    #[allow(dead_code)]
+    enum External {
+        Idle,
+        SetPoint(f32),
+        Move(i32, i32),
+        #[serde(rename_all = "UPPERCASE")]
+        Configure { rate_hz: u16 },
+        #[serde(skip)]
+        Internal,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(tag = "type")]
+    #[allow(dead_code)]
+    enum Internal {
+        Ping,
+        Reading { value: f64 },
+        Wrapped(Inner),
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    struct Inner {
+        id: u8,
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(tag = "t", content = "c", rename_all = "kebab-case")]
+    #[allow(dead_code)]
+    enum Adjacent {
+        PowerOff,
+        SetName(String),
+    }
+
+    #[derive(Serialize, CddlSchema)]
+    #[serde(untagged)]
+    #[allow(dead_code)]
+    enum Untagged {
+        Number(u64),
+        Text(String),
+        Nothing,
+    }
+
+    let mut generator = CddlGenerator::new();
+    generator.add::<External>();
+    generator.add::<Internal>();
+    generator.add::<Adjacent>();
+    generator.add::<Untagged>();
+    assert_eq!(
+        generator.to_string(),
+        "External = \"idle\" / { \"set_point\": float } / { \"move\": [int, int] } / \
+         { \"configure\": { \"RATE_HZ\": uint } }\n\
+         Internal = { \"type\": \"Ping\" } / { \"type\": \"Reading\", \"value\": float } / \
+         { \"type\": \"Wrapped\", ~Inner }\n\
+         Inner = { \"id\": uint }\n\
+         Adjacent = { \"t\": \"power-off\" } / { \"t\": \"set-name\", \"c\": tstr }\n\
+         Untagged = uint / tstr / null\n"
+    );
+
+    // fields of variants follow `rename_all_fields`, unless the variant has its own rule
+    #[derive(Serialize, CddlSchema)]
+    #[serde(tag = "type", rename_all_fields = "camelCase")]
+    #[allow(dead_code)]
+    enum Renamed {
+        Start { run_id: u32 },
+        #[serde(rename_all = "kebab-case")]
+        Stop { run_id: u32 },
+    }
+
+    let mut generator = CddlGenerator::new();
+    generator.add::<Renamed>();
+    assert_eq!(
+        generator.to_string(),
+        "Renamed = { \"type\": \"Start\", \"runId\": uint } / \
+         { \"type\": \"Stop\", \"run-id\": uint }\n"
+    );
+}
+
+#[cfg(feature = "cddl")]
+#[actix_rt::test]
+async fn test_cddl_spec() {
+    #[derive(Deserialize, CddlSchema)]
+    #[allow(dead_code)]
+    struct User {
+        name: String,
+    }
+
+    let spec = CddlSpec::new()
+        .request::<User>("POST /users/{id}")
+        .response::<Vec<User>>("GET /users");
+    assert_eq!(
+        spec.to_string(),
+        "; POST /users/{id} request\n\
+         post-users-id-request = User\n\
+         User = { \"name\": tstr }\n\
+         \n\
+         ; GET /users response\n\
+         get-users-response = [* User]\n"
+    );
+
+    let req = TestRequest::default().to_http_request();
+    let res = spec.respond_to(&req).await.unwrap();
+    assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "application/cddl");
+}
+
+#[cfg(feature = "cose")]
+#[actix_rt::test]
+async fn test_cose_mac0() {
+    use std::collections::HashMap;
+
+    let mut keys = HashMap::new();
+    keys.insert(b"sensor-1".to_vec(), b"0123456789abcdef0123456789abcdef".to_vec());
+    let keys = CoseKeys::new(keys);
+
+    let (req, _) = TestRequest::default().app_data(keys.clone()).to_http_parts();
+    let mut res = CoseMac0::new(MyObject::default())
+        .with_kid(b"sensor-1".to_vec())
+        .respond_to(&req)
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/cose; cose-type=\"cose-mac0\""
+    );
+    let message = actix_web::test::load_stream(res.take_body()).await.unwrap().to_vec();
+
+    // tag 17 around [protected, unprotected, payload, tag]
+    assert_eq!(message[0], 0xd1);
+    let value: serde_cbor::Value = serde_cbor::from_slice(&message[1..]).unwrap();
+    assert!(diagnostic(&value).starts_with("[h'a10105', {4: h'73656e736f722d31'}, h'"));
+
+    let extract = |body: Vec<u8>| {
+        let keys = keys.clone();
+        async move {
+            let (req, mut pl) = TestRequest::default()
+                .header(header::CONTENT_TYPE, "application/cose; cose-type=\"cose-mac0\"")
+                .app_data(keys)
+                .set_payload(body)
+                .to_http_parts();
+            CoseMac0::<MyObject>::from_request(&req, &mut pl).await
+        }
+    };
+
+    let object = extract(message.clone()).await.unwrap();
+    assert_eq!(object.kid(), Some(&b"sensor-1"[..]));
+    assert_eq!(object.into_inner(), MyObject::default());
+
+    // the COSE Content-Formats of CoAP are accepted here, unlike by `Cbor<T>`
+    let (req, mut pl) = TestRequest::default()
+        .header("coap-content-format", "17")
+        .app_data(keys.clone())
+        .app_data(CoapContentFormats::new())
+        .set_payload(message.clone())
+        .to_http_parts();
+    assert!(CoseMac0::<MyObject>::from_request(&req, &mut pl).await.is_ok());
+
+    // the tag is optional
+    assert!(extract(message[1..].to_vec()).await.is_ok());
+
+    // a changed payload no longer matches the tag
+    let mut tampered = message.clone();
+    let at = tampered.windows(4).position(|w| w == b"test").unwrap();
+    tampered[at] = b'b';
+    let err = extract(tampered).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::MacMismatch)));
+    assert_eq!(Response::from_error(err).status(), StatusCode::UNAUTHORIZED);
+
+    let other = message.windows(8).position(|w| w == b"sensor-1").unwrap();
+    let mut unknown = message.clone();
+    unknown[other + 7] = b'2';
+    let err = extract(unknown).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::UnknownKey)));
+    assert_eq!(Response::from_error(err).status(), StatusCode::UNAUTHORIZED);
+
+    let err = extract(get_test_bytes()).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::Cose)));
+    assert_eq!(Response::from_error(err).status(), StatusCode::BAD_REQUEST);
+
+    // responses need a known key
+    let err = CoseMac0::new(MyObject::default()).respond_to(&req).await.err().unwrap();
+    assert!(matches!(err, CborResponseError::UnknownKey));
+}
+
+#[cfg(feature = "cose")]
+#[actix_rt::test]
+async fn test_cose_replay() {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde_cbor::Value;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Command {
+        nonce: String,
+        ts: u64,
+        action: String,
+    }
+
+    let mut keys = HashMap::new();
+    keys.insert(b"sensor-1".to_vec(), b"first key".to_vec());
+    keys.insert(b"sensor-2".to_vec(), b"second key".to_vec());
+    let keys = CoseKeys::new(keys);
+    let guard = CoseReplayGuard::new()
+        .nonce_claim("nonce".to_owned())
+        .timestamp_claim("ts".to_owned())
+        .max_age(Duration::from_secs(60))
+        .clock_skew(Duration::from_secs(5));
+
+    let sign = |kid: &[u8], nonce: &str, ts: u64| {
+        let (req, _) = TestRequest::default().app_data(keys.clone()).to_http_parts();
+        let command = Command { nonce: nonce.to_owned(), ts, action: "open".to_owned() };
+        let res = CoseMac0::new(command).with_kid(kid.to_vec()).respond_to(&req);
+        async move {
+            let mut res = res.await.unwrap();
+            actix_web::test::load_stream(res.take_body()).await.unwrap()
+        }
+    };
+    let extract = |message: Bytes| {
+        let (req, mut pl) = TestRequest::default()
+            .header(header::CONTENT_TYPE, "application/cose")
+            .app_data(keys.clone())
+            .app_data(guard.clone())
+            .set_payload(message)
+            .to_http_parts();
+        async move { CoseMac0::<Command>::from_request(&req, &mut pl).await }
+    };
+    let status = |err: actix_web::Error| Response::from_error(err).status();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+    let message = sign(b"sensor-1", "a", now).await;
+    assert_eq!(extract(message.clone()).await.unwrap().action, "open");
+
+    let err = extract(message).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::Replayed)));
+    assert_eq!(status(err), StatusCode::UNAUTHORIZED);
+
+    // nonces are per key
+    assert!(extract(sign(b"sensor-2", "a", now).await).await.is_ok());
+
+    // within the clock skew, in either direction
+    assert!(extract(sign(b"sensor-1", "b", now - 64).await).await.is_ok());
+    assert!(extract(sign(b"sensor-1", "c", now + 4).await).await.is_ok());
+
+    for &ts in &[now - 120, now + 60, u64::MAX] {
+        let err = extract(sign(b"sensor-1", "d", ts).await).await.err().unwrap();
+        assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::Expired)));
+        assert_eq!(status(err), StatusCode::UNAUTHORIZED);
+    }
+
+    // a message without claims, authenticated with the right key
+    let (req, _) = TestRequest::default().app_data(keys.clone()).to_http_parts();
+    let res = CoseMac0::new(MyObject::default()).with_kid(b"sensor-1".to_vec()).respond_to(&req);
+    let mut res = res.await.unwrap();
+    let message = actix_web::test::load_stream(res.take_body()).await.unwrap();
+    let err = extract(message).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::MissingClaim)));
+    assert_eq!(status(err), StatusCode::BAD_REQUEST);
+
+    // text and byte string nonces with the same bytes are different nonces
+    let claims = |nonce: Value| {
+        let mut claims = std::collections::BTreeMap::new();
+        claims.insert(Value::Text("nonce".to_owned()), nonce);
+        claims.insert(Value::Text("ts".to_owned()), Value::Integer(now.into()));
+        claims.insert(Value::Text("action".to_owned()), Value::Text("open".to_owned()));
+        let (req, _) = TestRequest::default().app_data(keys.clone()).to_http_parts();
+        let res = CoseMac0::new(Value::Map(claims)).with_kid(b"sensor-1".to_vec()).respond_to(&req);
+        async move {
+            let mut res = res.await.unwrap();
+            actix_web::test::load_stream(res.take_body()).await.unwrap()
+        }
+    };
+    assert!(extract(claims(Value::Text("f".to_owned())).await).await.is_ok());
+    assert!(extract(claims(Value::Bytes(b"f".to_vec())).await).await.is_ok());
+    let err = extract(claims(Value::Bytes(b"f".to_vec())).await).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::Replayed)));
+
+    // a window too large to represent never expires instead of overflowing
+    let long_guard = guard.clone().max_age(Duration::MAX).clock_skew(Duration::MAX);
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cose")
+        .app_data(keys.clone())
+        .app_data(long_guard)
+        .set_payload(sign(b"sensor-1", "g", now).await)
+        .to_http_parts();
+    assert!(CoseMac0::<Command>::from_request(&req, &mut pl).await.is_ok());
+
+    // expired nonces can be reused, as their messages are rejected by timestamp
+    let cache = LruReplayCache::new(10);
+    assert!(cache.insert(None, b"a", SystemTime::now() - Duration::from_secs(1)));
+    assert!(cache.insert(None, b"a", SystemTime::now() + Duration::from_secs(60)));
+    assert!(!cache.insert(None, b"a", SystemTime::now() + Duration::from_secs(60)));
+
+    // a single entry still refuses an immediate replay
+    let guard = guard.cache(LruReplayCache::new(1));
+    let message = sign(b"sensor-1", "e", now).await;
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cose")
+        .app_data(keys.clone())
+        .app_data(guard.clone())
+        .set_payload(message.clone())
+        .to_http_parts();
+    assert!(CoseMac0::<Command>::from_request(&req, &mut pl).await.is_ok());
+    let (req, mut pl) = TestRequest::default()
+        .header(header::CONTENT_TYPE, "application/cose")
+        .app_data(keys.clone())
+        .app_data(guard)
+        .set_payload(message)
+        .to_http_parts();
+    let err = CoseMac0::<Command>::from_request(&req, &mut pl).await.err().unwrap();
+    assert!(matches!(err.as_error::<CborPayloadError>(), Some(CborPayloadError::Replayed)));
+}
+
+#[cfg(feature = "cose")]
+#[test]
+#[should_panic(expected = "capacity must be at least 1")]
+fn test_lru_replay_cache_empty() {
+    LruReplayCache::new(0);
+}
+
+#[actix_rt::test]
+async fn test_cbor_error_response() {
+    #[derive(Debug)]
+    enum DomainError {
+        NotFound(String),
+    }
+
+    impl IntoCborErrorResponse for DomainError {
+        type Body = MyObject;
+
+        fn into_cbor_error_response(self) -> CborErrorResponse<MyObject> {
+            match self {
+                DomainError::NotFound(name) => {
+                    CborErrorResponse::new(StatusCode::NOT_FOUND, MyObject { name, number: 404 })
+                        .content_type("application/problem+cbor")
+                }
+            }
+        }
+    }
+
+    fn handler() -> Result<Cbor<MyObject>, CborErrorResponse<MyObject>> {
+        Err(DomainError::NotFound("test".to_owned()))?
+    }
+
+    let err = handler().err().unwrap();
+    assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    assert_eq!(err.error().number, 404);
+
+    let mut res = Response::from_error(err.into());
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+cbor"
+    );
+    let body = actix_web::test::load_stream(res.take_body()).await.unwrap();
+    let body: MyObject = serde_cbor::from_slice(&body).unwrap();
+    assert_eq!(body, MyObject { name: "test".to_owned(), number: 404 });
+
+    // errors that can't be encoded are a bare 500
+    #[derive(Debug)]
+    struct Unserializable;
+
+    impl serde::Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    let err = CborErrorResponse::new(StatusCode::BAD_REQUEST, Unserializable);
+    let res = Response::from_error(err.into());
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(res.headers().get(header::CONTENT_TYPE).is_none());
+}
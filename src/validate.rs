@@ -0,0 +1,291 @@
+use std::fmt;
+
+/// Default max nesting depth of the incremental decoder, the same as the recursion limit of
+/// `serde_cbor`.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Reasons the incremental decoder rejected a payload, each with the offset of the offending item.
+#[derive(Debug)]
+pub enum MalformedError {
+    /// An item header is not well-formed CBOR
+    InvalidHeader(usize),
+    /// A break code outside of an indefinite length item, or closing a map with an odd number of
+    /// entries
+    UnexpectedBreak(usize),
+    /// Arrays, maps and tags are nested deeper than allowed (default: 128)
+    TooDeep(usize),
+    /// A declared length does not fit in the payload limit
+    TooLong(usize),
+    /// Data after the end of the top level item
+    TrailingData(usize),
+}
+
+impl MalformedError {
+    /// Offset in the payload of the item that was rejected.
+    pub fn offset(&self) -> usize {
+        match *self {
+            MalformedError::InvalidHeader(offset)
+            | MalformedError::UnexpectedBreak(offset)
+            | MalformedError::TooDeep(offset)
+            | MalformedError::TooLong(offset)
+            | MalformedError::TrailingData(offset) => offset,
+        }
+    }
+}
+
+impl fmt::Display for MalformedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MalformedError::InvalidHeader(offset) => {
+                write!(f, "Invalid CBOR item header at offset {}", offset)
+            }
+            MalformedError::UnexpectedBreak(offset) => {
+                write!(f, "Unexpected CBOR break code at offset {}", offset)
+            }
+            MalformedError::TooDeep(offset) => {
+                write!(f, "CBOR nesting is deeper than allowed at offset {}", offset)
+            }
+            MalformedError::TooLong(offset) => {
+                write!(f, "CBOR item at offset {} is longer than allowed", offset)
+            }
+            MalformedError::TrailingData(offset) => {
+                write!(f, "Trailing data after CBOR item at offset {}", offset)
+            }
+        }
+    }
+}
+
+enum Frame {
+    /// Definite length array, map or tag, with the number of items left
+    Items(u64),
+    /// Indefinite length array or map, with the number of items so far
+    Indefinite { map: bool, items: u64 },
+    /// Indefinite length byte or text string, with its major type
+    Chunks(u8),
+}
+
+/// Structural CBOR validator that is fed a payload chunk by chunk.
+///
+/// Checks that the payload is a single well-formed item, that nesting stays within `max_depth`
+/// and that declared lengths fit in what is left of `limit`, without decoding any values. Errors
+/// are reported at the first offending item, before the rest of the payload is read.
+pub(crate) struct Validator {
+    limit: usize,
+    max_depth: usize,
+    stack: Vec<Frame>,
+    header: [u8; 9],
+    header_len: usize,
+    skip: u64,
+    offset: usize,
+    done: bool,
+    sequence: bool,
+}
+
+impl Validator {
+    pub(crate) fn new(limit: usize, max_depth: usize) -> Self {
+        Validator {
+            limit,
+            max_depth,
+            stack: Vec::new(),
+            header: [0; 9],
+            header_len: 0,
+            skip: 0,
+            offset: 0,
+            done: false,
+            sequence: false,
+        }
+    }
+
+    /// Accept a CBOR sequence of any number of items instead of a single item.
+    pub(crate) fn sequence(mut self) -> Self {
+        self.sequence = true;
+        self
+    }
+
+    /// Validate the next chunk of the payload.
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Result<(), MalformedError> {
+        let mut pos = 0;
+        while pos < chunk.len() {
+            pos += self.step(&chunk[pos..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Validate the next chunk of a sequence up to the end of its current item, returning the
+    /// number of bytes of `chunk` up to and including the end of the item, or `None` if the item
+    /// continues past the chunk. The limit and error offsets apply to each item on its own.
+    pub(crate) fn feed_item(&mut self, chunk: &[u8]) -> Result<Option<usize>, MalformedError> {
+        let mut pos = 0;
+        while pos < chunk.len() {
+            pos += self.step(&chunk[pos..])?;
+            if self.done {
+                self.done = false;
+                self.offset = 0;
+                return Ok(Some(pos));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Validate the start of `chunk`, returning the number of bytes consumed.
+    fn step(&mut self, chunk: &[u8]) -> Result<usize, MalformedError> {
+        // contents of a byte or text string
+        if self.skip > 0 {
+            let n = self.skip.min(chunk.len() as u64) as usize;
+            self.offset += n;
+            self.skip -= n as u64;
+            if self.skip == 0 {
+                self.item_done();
+            }
+            return Ok(n);
+        }
+
+        if self.done && !self.sequence {
+            return Err(MalformedError::TrailingData(self.offset));
+        }
+
+        // item headers may be split across chunks
+        self.header[self.header_len] = chunk[0];
+        self.header_len += 1;
+        self.offset += 1;
+
+        let start = self.offset - self.header_len;
+        let size = header_size(self.header[0]).ok_or(MalformedError::InvalidHeader(start))?;
+        if self.header_len == size {
+            self.header_len = 0;
+            self.item(start)?;
+        }
+
+        Ok(1)
+    }
+
+    /// Handle a complete item header starting at `start`.
+    fn item(&mut self, start: usize) -> Result<(), MalformedError> {
+        let initial = self.header[0];
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+        let indefinite = info == 31;
+        let arg = match info {
+            24 => self.header[1] as u64,
+            25 => u16::from_be_bytes([self.header[1], self.header[2]]) as u64,
+            26 => {
+                u32::from_be_bytes([self.header[1], self.header[2], self.header[3], self.header[4]]) as u64
+            }
+            27 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&self.header[1..9]);
+                u64::from_be_bytes(bytes)
+            }
+            info => info as u64,
+        };
+
+        // indefinite length strings consist of definite length strings of the same type only
+        if let Some(Frame::Chunks(chunk_major)) = self.stack.last() {
+            if initial != 0xff && (major != *chunk_major || indefinite) {
+                return Err(MalformedError::InvalidHeader(start));
+            }
+        }
+
+        match major {
+            0 | 1 if indefinite => return Err(MalformedError::InvalidHeader(start)),
+            0 | 1 => self.item_done(),
+            2 | 3 if indefinite => self.push(Frame::Chunks(major), start)?,
+            2 | 3 => {
+                self.check_length(arg, start)?;
+                if arg == 0 {
+                    self.item_done();
+                } else {
+                    self.skip = arg;
+                }
+            }
+            4 | 5 if indefinite => self.push(Frame::Indefinite { map: major == 5, items: 0 }, start)?,
+            4 | 5 => {
+                let items = if major == 5 { arg.saturating_mul(2) } else { arg };
+                self.check_length(items, start)?;
+                if items == 0 {
+                    self.item_done();
+                } else {
+                    self.push(Frame::Items(items), start)?;
+                }
+            }
+            6 if indefinite => return Err(MalformedError::InvalidHeader(start)),
+            6 => self.push(Frame::Items(1), start)?,
+            _ => match info {
+                // two byte simple values below 32 are not well-formed
+                24 if arg < 32 => return Err(MalformedError::InvalidHeader(start)),
+                31 => self.close(start)?,
+                _ => self.item_done(),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Check that `len` bytes or items, at least a byte each, fit in the rest of the limit.
+    fn check_length(&self, len: u64, start: usize) -> Result<(), MalformedError> {
+        if len > self.limit.saturating_sub(self.offset) as u64 {
+            Err(MalformedError::TooLong(start))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn push(&mut self, frame: Frame, start: usize) -> Result<(), MalformedError> {
+        if self.stack.len() >= self.max_depth {
+            return Err(MalformedError::TooDeep(start));
+        }
+
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    /// Handle a break code, which ends the innermost indefinite length item.
+    fn close(&mut self, start: usize) -> Result<(), MalformedError> {
+        match self.stack.pop() {
+            Some(Frame::Chunks(_)) => {}
+            Some(Frame::Indefinite { map, items }) if !map || items % 2 == 0 => {}
+            _ => return Err(MalformedError::UnexpectedBreak(start)),
+        }
+
+        self.item_done();
+        Ok(())
+    }
+
+    /// Count a complete item towards its parents.
+    fn item_done(&mut self) {
+        loop {
+            match self.stack.last_mut() {
+                None => {
+                    self.done = true;
+                    return;
+                }
+                Some(Frame::Items(items)) => {
+                    *items -= 1;
+                    if *items > 0 {
+                        return;
+                    }
+                    self.stack.pop();
+                }
+                Some(Frame::Indefinite { items, .. }) => {
+                    *items += 1;
+                    return;
+                }
+                Some(Frame::Chunks(_)) => return,
+            }
+        }
+    }
+}
+
+/// Size of an item header from its initial byte, or `None` if it is reserved.
+fn header_size(initial: u8) -> Option<usize> {
+    match initial & 0x1f {
+        0..=23 | 31 => Some(1),
+        24 => Some(2),
+        25 => Some(3),
+        26 => Some(5),
+        27 => Some(9),
+        _ => None,
+    }
+}
@@ -0,0 +1,352 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::http::StatusCode;
+use actix_http::ws::{CloseCode, CloseReason, Codec, Frame, Item, Message, ProtocolError};
+use actix_web::HttpRequest;
+use actix_web::web::{Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{CborConfig, CborPayloadError, CborResponseError, DEFAULT_LIMIT};
+
+/// A WebSocket message carrying CBOR data, or a control message.
+#[derive(Debug, PartialEq)]
+pub enum CborWsMessage<T> {
+    /// CBOR document, sent as a binary message
+    Cbor(T),
+    /// Ping message
+    Ping(Bytes),
+    /// Pong message
+    Pong(Bytes),
+    /// Close message with optional reason
+    Close(Option<CloseReason>),
+}
+
+/// Errors that can occur while decoding or encoding CBOR WebSocket messages.
+#[derive(Debug)]
+pub enum CborWsError {
+    /// WebSocket protocol error
+    Protocol(ProtocolError),
+    /// Incoming message could not be decoded
+    Payload(CborPayloadError),
+    /// Outgoing message could not be encoded
+    Response(CborResponseError),
+}
+
+impl CborWsError {
+    /// Reason to close the connection with after this error.
+    pub fn close_reason(&self) -> CloseReason {
+        let code = match self {
+            CborWsError::Protocol(ProtocolError::Overflow) => CloseCode::Size,
+            CborWsError::Protocol(_) => CloseCode::Protocol,
+            CborWsError::Payload(CborPayloadError::Overflow) => CloseCode::Size,
+            CborWsError::Payload(CborPayloadError::ContentType) => CloseCode::Unsupported,
+            CborWsError::Payload(_) => CloseCode::Invalid,
+            CborWsError::Response(_) => CloseCode::Error,
+        };
+
+        (code, self.to_string().trim_end()).into()
+    }
+}
+
+impl From<ProtocolError> for CborWsError {
+    fn from(e: ProtocolError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+impl From<io::Error> for CborWsError {
+    fn from(e: io::Error) -> Self {
+        Self::Protocol(e.into())
+    }
+}
+
+impl From<CborPayloadError> for CborWsError {
+    fn from(e: CborPayloadError) -> Self {
+        Self::Payload(e)
+    }
+}
+
+impl From<CborResponseError> for CborWsError {
+    fn from(e: CborResponseError) -> Self {
+        Self::Response(e)
+    }
+}
+
+impl fmt::Display for CborWsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CborWsError::Protocol(inner) => write!(f, "WebSocket protocol error: {}", inner),
+            CborWsError::Payload(inner) => write!(f, "{}", inner),
+            CborWsError::Response(inner) => write!(f, "{}", inner),
+        }
+    }
+}
+
+impl Error for CborWsError {}
+
+/// WebSocket codec for CBOR messages, decoding incoming binary messages as `I` and encoding
+/// outgoing ones from `O`.
+///
+/// Fragmented messages are reassembled, and continuation frames that do not continue a message, or
+/// new messages before the last one was finished, are protocol errors. Incoming messages are
+/// limited to the [`CborConfig::limit`](struct.CborConfig.html#method.limit) and outgoing ones to the
+/// [`CborConfig::response_limit`](struct.CborConfig.html#method.response_limit), if any. Text
+/// messages fail with
+/// [`CborPayloadError::ContentType`](enum.CborPayloadError.html#variant.ContentType), and the rest
+/// of a rejected fragmented message is skipped.
+///
+/// The codec can be used on a `Framed` WebSocket connection in place of `ws::Codec`. In an actor,
+/// [`decode_message`](#method.decode_message) and [`encode_message`](#method.encode_message)
+/// convert to and from the `ws::Message`s it handles. Either way, errors provide a
+/// [`close_reason`](enum.CborWsError.html#method.close_reason) to end the connection with.
+///
+/// # Example
+/// ```
+/// use actix_cbor::{CborWsCodec, CborWsMessage};
+/// use actix_http::ws;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize)]
+/// enum Command {
+///     Subscribe(String),
+/// }
+///
+/// #[derive(Serialize)]
+/// struct Reading {
+///     sensor: String,
+///     value: f64,
+/// }
+///
+/// fn handle(
+///     codec: &mut CborWsCodec<Command, Reading>,
+///     msg: ws::Message,
+/// ) -> Option<ws::Message> {
+///     match codec.decode_message(msg) {
+///         Ok(Some(CborWsMessage::Cbor(Command::Subscribe(sensor)))) => {
+///             codec.encode_message(&Reading { sensor, value: 0.0 }).ok()
+///         }
+///         Ok(_) => None,
+///         Err(e) => Some(ws::Message::Close(Some(e.close_reason()))),
+///     }
+/// }
+/// ```
+pub struct CborWsCodec<I, O> {
+    codec: Codec,
+    limit: usize,
+    response_limit: Option<usize>,
+    fragments: Fragments,
+    _types: PhantomData<fn(O) -> I>,
+}
+
+/// State of a fragmented message.
+enum Fragments {
+    /// No fragmented message is in progress
+    Idle,
+    /// A binary message is being reassembled
+    Binary(BytesMut),
+    /// The rest of a message that was rejected, such as a text message, is being skipped
+    Rejected,
+}
+
+impl<I, O> Default for CborWsCodec<I, O> {
+    fn default() -> Self {
+        CborWsCodec {
+            codec: Codec::new().max_size(DEFAULT_LIMIT),
+            limit: DEFAULT_LIMIT,
+            response_limit: None,
+            fragments: Fragments::Idle,
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<I, O> CborWsCodec<I, O> {
+    /// Create a server codec with the default limit of 32kB for incoming messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a server codec with the limits of the `CborConfig` for `I` registered on the app.
+    pub fn from_req(req: &HttpRequest) -> Self
+        where
+            I: 'static,
+    {
+        let config = CborConfig::from_req_for::<I>(req);
+
+        Self::new()
+            .limit(config.limit)
+            .response_limit(config.response_limit)
+    }
+
+    /// Change the maximum size of incoming messages, including fragmented ones.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.codec = self.codec.max_size(limit);
+        self.limit = limit;
+        self
+    }
+
+    /// Change the maximum size of outgoing messages. By default there is no limit.
+    pub fn response_limit(mut self, limit: impl Into<Option<usize>>) -> Self {
+        self.response_limit = limit.into();
+        self
+    }
+
+    /// Set the codec to client mode, for connections made with `awc`.
+    pub fn client_mode(mut self) -> Self {
+        self.codec = self.codec.client_mode();
+        self
+    }
+
+    /// Decode a message handled by an actor. `Ok(None)` if the message is a fragment of a
+    /// message that is not complete yet.
+    pub fn decode_message(&mut self, msg: Message) -> Result<Option<CborWsMessage<I>>, CborWsError>
+        where
+            I: DeserializeOwned,
+    {
+        let frame = match msg {
+            Message::Text(text) => Frame::Text(Bytes::from(text)),
+            Message::Binary(data) => Frame::Binary(data),
+            Message::Continuation(item) => Frame::Continuation(item),
+            Message::Ping(data) => Frame::Ping(data),
+            Message::Pong(data) => Frame::Pong(data),
+            Message::Close(reason) => Frame::Close(reason),
+            Message::Nop => return Ok(None),
+        };
+
+        self.decode_frame(frame)
+    }
+
+    /// Encode an outgoing value as a binary message.
+    pub fn encode_message(&self, value: &O) -> Result<Message, CborWsError>
+        where
+            O: Serialize,
+    {
+        let body = serde_cbor::to_vec(value).map_err(CborResponseError::from)?;
+
+        if let Some(limit) = self.response_limit {
+            if body.len() > limit {
+                return Err(CborResponseError::Overflow {
+                    size: body.len(),
+                    limit,
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                }
+                    .into());
+            }
+        }
+
+        Ok(Message::Binary(Bytes::from(body)))
+    }
+
+    fn decode_frame(&mut self, frame: Frame) -> Result<Option<CborWsMessage<I>>, CborWsError>
+        where
+            I: DeserializeOwned,
+    {
+        let data = match frame {
+            Frame::Binary(_) | Frame::Text(_) | Frame::Continuation(Item::FirstBinary(_))
+            | Frame::Continuation(Item::FirstText(_))
+            if !matches!(self.fragments, Fragments::Idle) =>
+            {
+                self.fragments = Fragments::Idle;
+                return Err(ProtocolError::ContinuationStarted.into());
+            }
+            Frame::Binary(data) => data,
+            Frame::Text(_) => return Err(CborPayloadError::ContentType.into()),
+            Frame::Continuation(Item::FirstText(_)) => {
+                self.fragments = Fragments::Rejected;
+                return Err(CborPayloadError::ContentType.into());
+            }
+            Frame::Continuation(Item::FirstBinary(data)) => {
+                self.fragments = Fragments::Binary(BytesMut::new());
+                self.append(&data)?;
+                return Ok(None);
+            }
+            Frame::Continuation(Item::Continue(data)) => {
+                self.append(&data)?;
+                return Ok(None);
+            }
+            Frame::Continuation(Item::Last(data)) => {
+                // the message ends here even if the last fragment is rejected
+                let appended = self.append(&data);
+                let fragments = mem::replace(&mut self.fragments, Fragments::Idle);
+                appended?;
+
+                match fragments {
+                    Fragments::Binary(buf) => buf.freeze(),
+                    _ => return Ok(None),
+                }
+            }
+            Frame::Ping(data) => return Ok(Some(CborWsMessage::Ping(data))),
+            Frame::Pong(data) => return Ok(Some(CborWsMessage::Pong(data))),
+            Frame::Close(reason) => return Ok(Some(CborWsMessage::Close(reason))),
+        };
+
+        if data.len() > self.limit {
+            return Err(CborPayloadError::Overflow.into());
+        }
+
+        let value = serde_cbor::from_slice(&data).map_err(CborPayloadError::from)?;
+        Ok(Some(CborWsMessage::Cbor(value)))
+    }
+
+    /// Append a fragment to the message being reassembled, or skip it if the message was rejected.
+    fn append(&mut self, data: &[u8]) -> Result<(), CborWsError> {
+        let buf = match self.fragments {
+            Fragments::Idle => return Err(ProtocolError::ContinuationNotStarted.into()),
+            Fragments::Binary(ref mut buf) => buf,
+            Fragments::Rejected => return Ok(()),
+        };
+
+        if buf.len() + data.len() > self.limit {
+            self.fragments = Fragments::Rejected;
+            return Err(CborPayloadError::Overflow.into());
+        }
+
+        buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<I, O> Decoder for CborWsCodec<I, O> where I: DeserializeOwned {
+    type Item = CborWsMessage<I>;
+    type Error = CborWsError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(frame) = self.codec.decode(src)? {
+            if let Some(msg) = self.decode_frame(frame)? {
+                return Ok(Some(msg));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<I, O> Encoder<CborWsMessage<O>> for CborWsCodec<I, O> where O: Serialize {
+    type Error = CborWsError;
+
+    fn encode(&mut self, item: CborWsMessage<O>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let msg = match item {
+            CborWsMessage::Cbor(value) => self.encode_message(&value)?,
+            CborWsMessage::Ping(data) => Message::Ping(data),
+            CborWsMessage::Pong(data) => Message::Pong(data),
+            CborWsMessage::Close(reason) => Message::Close(reason),
+        };
+
+        Ok(self.codec.encode(msg, dst)?)
+    }
+}
+
+impl<I, O> fmt::Debug for CborWsCodec<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CborWsCodec")
+            .field("limit", &self.limit)
+            .field("response_limit", &self.response_limit)
+            .finish()
+    }
+}